@@ -1,19 +1,29 @@
 
-use std::collections::{HashSet, BTreeSet};
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::collections::BTreeSet;
+use criterion::{criterion_group, criterion_main, Criterion};
 use fhq_treap::TreapSet;
 
 fn criterion_benchmark(c: &mut Criterion) {
+    // Keys are drawn up front so that `rand`'s generator stays out of the
+    // timed loops.
+    let keys: Vec<u8> = (0..100).map(|_| rand::random()).collect();
     c.bench_function("HashSet insert", |b| b.iter(|| {
         let mut h = BTreeSet::<u8>::new();
-        for _ in 0..100 {
-            h.insert(rand::random());
+        for &key in &keys {
+            h.insert(key);
         }
     }));
     c.bench_function("TreapSet insert", |b| b.iter(|| {
         let mut h = TreapSet::<u8>::new();
-        for _ in 0..100 {
-            h.insert(rand::random());
+        for &key in &keys {
+            h.insert(key);
+        }
+    }));
+    #[cfg(feature = "chunked")]
+    c.bench_function("ChunkedTreapMap insert", |b| b.iter(|| {
+        let mut h = fhq_treap::ChunkedTreapMap::<u8, ()>::new();
+        for &key in &keys {
+            h.insert(key, ());
         }
     }));
 }
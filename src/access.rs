@@ -0,0 +1,231 @@
+use std::{
+    borrow::Borrow,
+    cell::Cell,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+};
+
+use crate::{Iter, NodeData, Size, TreapMap};
+
+// A `TreapMap` that counts the lookups of each entry, so that
+// `rebuild_weighted` can move the frequently read ones towards the root. The
+// counts live next to the values, in `Cell`s, which makes the map `!Sync`.
+pub struct AccessCountedTreapMap<K: Ord, V> {
+    map: TreapMap<K, (V, Cell<u64>)>,
+}
+
+// Adds or strips the counts, keeping the shape and weights of the tree.
+fn map_values<K: Ord, V, W>(x: TreapMap<K, V>, f: &mut impl FnMut(V) -> W) -> TreapMap<K, W> {
+    let Some(node) = x.into_root() else {
+        return TreapMap::new();
+    };
+    let NodeData {
+        children: [left, right],
+        size,
+        key,
+        value,
+        weight,
+    } = *node;
+    count!(allocations);
+    TreapMap(Some(Box::new(NodeData {
+        children: [map_values(left, f), map_values(right, f)],
+        size,
+        key,
+        value: f(value),
+        weight,
+    })))
+}
+
+impl<K: Ord, V> Default for AccessCountedTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for AccessCountedTreapMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for AccessCountedTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V> From<TreapMap<K, V>> for AccessCountedTreapMap<K, V> {
+    fn from(map: TreapMap<K, V>) -> Self {
+        Self {
+            map: map_values(map, &mut |value| (value, Cell::new(0))),
+        }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for AccessCountedTreapMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for AccessCountedTreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a AccessCountedTreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = AccessIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> AccessCountedTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            map: TreapMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.map.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map = TreapMap::new();
+    }
+
+    pub fn into_map(self) -> TreapMap<K, V> {
+        map_values(self.map, &mut |(value, _)| value)
+    }
+
+    // Lookups through `get`, `get_mut` and `contains_key` each count once.
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        let (value, count) = self.map.get(key)?;
+        count.set(count.get() + 1);
+        Some(value)
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let (value, count) = self.map.get_mut(key)?;
+        *count.get_mut() += 1;
+        Some(value)
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    // Replacing a value keeps its count.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.map.get_mut(&key) {
+            Some(it) => Some(std::mem::replace(&mut it.0, value)),
+            None => {
+                self.map.insert(key, (value, Cell::new(0)));
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.map.remove(key).map(|it| it.0)
+    }
+
+    // The number of lookups of `key` so far, which doesn't count as one.
+    #[inline]
+    pub fn access_count<Q: Ord + ?Sized>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get(key).map_or(0, |it| it.1.get())
+    }
+
+    pub fn reset_counts(&mut self) {
+        for (_, (_, count)) in self.map.iter_mut() {
+            *count.get_mut() = 0;
+        }
+    }
+
+    // See `TreapMap::rebuild_weighted`. The counts are kept, so later
+    // rebuilds weigh the whole history unless `reset_counts` is called.
+    pub fn rebuild_weighted(&mut self) {
+        self.map.rebuild_weighted(|_, (_, count)| count.get());
+    }
+
+    #[inline]
+    pub fn iter(&self) -> AccessIter<'_, K, V> {
+        AccessIter(self.map.iter())
+    }
+}
+
+pub struct AccessIter<'a, K: Ord, V>(Iter<'a, K, (V, Cell<u64>)>);
+
+impl<'a, K: Ord, V> Clone for AccessIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for AccessIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, (value, _))| (key, value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for AccessIter<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(key, (value, _))| (key, value))
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for AccessIter<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for AccessIter<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for AccessIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
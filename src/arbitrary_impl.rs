@@ -0,0 +1,41 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{NodeData, TreapMap, TreapSet};
+
+// Treaps are built by replaying an arbitrary sequence of operations, with the
+// node weights taken from the input as well, so every shape reachable through
+// the public API can be produced and a given input always yields the same tree.
+impl<'a, K: Ord + Arbitrary<'a>, V: Arbitrary<'a>> Arbitrary<'a> for TreapMap<K, V> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut map = Self::new();
+        for _ in 0..u.arbitrary_len::<(u8, K, V)>()? {
+            match u.int_in_range(0..=7u8)? {
+                0..=4 => {
+                    let key = K::arbitrary(u)?;
+                    let value = V::arbitrary(u)?;
+                    let mut node = NodeData::new(key, value);
+                    node.weight = u.arbitrary()?;
+                    let (l, _, r) = std::mem::take(&mut map).split3(&node.key);
+                    map = Self::merge(Self::merge(l, node.into()), r);
+                }
+                5 => {
+                    map.remove(&K::arbitrary(u)?);
+                }
+                6 => {
+                    map.pop_min();
+                }
+                _ => {
+                    map.pop_max();
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl<'a, K: Ord + Arbitrary<'a>> Arbitrary<'a> for TreapSet<K> {
+    #[inline]
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        TreapMap::arbitrary(u).map(Self)
+    }
+}
@@ -0,0 +1,473 @@
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::TryReserveError,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+    mem,
+};
+
+use crate::{rng, Weight};
+
+// Slot 0 is a sentinel with size 0 standing in for "no child", which keeps
+// size lookups branch-free.
+const NIL: u32 = 0;
+
+// A vacant slot has no entry, and its `left` links to the next vacant slot.
+struct Node<K, V> {
+    left: u32,
+    right: u32,
+    size: u32,
+    entry: Option<(K, V)>,
+
+    weight: Weight,
+}
+
+impl<K, V> Node<K, V> {
+    #[inline]
+    fn key(&self) -> &K {
+        &self.entry.as_ref().unwrap().0
+    }
+}
+
+// A treap whose nodes live in one `Vec` and link to each other by index.
+// Removed nodes leave vacant slots that later inserts reuse, so a map that
+// churns at a steady size stops allocating once the `Vec` has grown.
+pub struct ArenaTreapMap<K: Ord, V> {
+    nodes: Vec<Node<K, V>>,
+    free: u32,
+    root: u32,
+    path: Vec<(u32, bool)>,
+}
+
+impl<K: Ord, V> Default for ArenaTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for ArenaTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V> ArenaTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            // One extra for the sentinel.
+            nodes: Vec::with_capacity(if capacity == 0 { 0 } else { capacity + 1 }),
+            free: NIL,
+            root: NIL,
+            path: Vec::new(),
+        }
+    }
+
+    // The number of entries the map can hold without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity().saturating_sub(1)
+    }
+
+    // Makes room for `additional` more entries beyond those already stored,
+    // counting vacant slots as free.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let used = self.nodes.len().max(1) - 1;
+        let needed = (self.len() as usize + additional).saturating_sub(used);
+        self.nodes.try_reserve(needed + self.nodes.is_empty() as usize)
+    }
+
+    // Counts the whole node buffer, vacant slots and spare capacity included,
+    // plus the scratch path.
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<Node<K, V>>()
+            + self.path.capacity() * mem::size_of::<(u32, bool)>()
+    }
+
+    #[inline]
+    pub fn len(&self) -> u32 {
+        if self.root == NIL {
+            0
+        } else {
+            self.nodes[self.root as usize].size
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.root == NIL
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free = NIL;
+        self.root = NIL;
+    }
+
+    #[inline]
+    fn node(&self, x: u32) -> &Node<K, V> {
+        &self.nodes[x as usize]
+    }
+
+    #[inline]
+    fn node_mut(&mut self, x: u32) -> &mut Node<K, V> {
+        &mut self.nodes[x as usize]
+    }
+
+    #[inline]
+    fn maintain(&mut self, x: u32) {
+        let node = self.node(x);
+        let size = self.node(node.left).size + self.node(node.right).size + 1;
+        self.node_mut(x).size = size;
+    }
+
+    fn alloc(&mut self, key: K, value: V) -> u32 {
+        let node = Node {
+            left: NIL,
+            right: NIL,
+            size: 1,
+            entry: Some((key, value)),
+
+            weight: rng::random_weight(),
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                left: NIL,
+                right: NIL,
+                size: 0,
+                entry: None,
+
+                weight: 0,
+            });
+        }
+        if self.free == NIL {
+            // Slot 0 is the sentinel, so this leaves `u32::MAX - 1` entries,
+            // whose count still fits the `u32` sizes.
+            assert!(self.nodes.len() < u32::MAX as usize, "arena is full");
+            let x = self.nodes.len() as u32;
+            self.nodes.push(node);
+            x
+        } else {
+            let x = self.free;
+            self.free = mem::replace(self.node_mut(x), node).left;
+            x
+        }
+    }
+
+    fn dealloc(&mut self, x: u32) -> (K, V) {
+        let free = mem::replace(&mut self.free, x);
+        let node = self.node_mut(x);
+        node.left = free;
+        node.right = NIL;
+        node.entry.take().unwrap()
+    }
+
+    // Indices make an explicit path cheap, so unlike `TreapMap` this never
+    // recurses: walk down recording which side each node goes to, then relink
+    // bottom-up. `path` is kept between calls to avoid reallocating it.
+    fn split_by(&mut self, x: u32, mut left: impl FnMut(&Self, &Node<K, V>) -> bool) -> (u32, u32) {
+        let mut path = mem::take(&mut self.path);
+        let mut cur = x;
+        while cur != NIL {
+            let node = self.node(cur);
+            let side = left(self, node);
+            path.push((cur, side));
+            cur = if side { node.right } else { node.left };
+        }
+        let (mut l, mut r) = (NIL, NIL);
+        for (x, side) in path.drain(..).rev() {
+            if side {
+                self.node_mut(x).right = l;
+                l = x;
+            } else {
+                self.node_mut(x).left = r;
+                r = x;
+            }
+            self.maintain(x);
+        }
+        self.path = path;
+        (l, r)
+    }
+
+    fn split_lt<Q: Ord + ?Sized>(&mut self, x: u32, key: &Q) -> (u32, u32)
+    where
+        K: Borrow<Q>,
+    {
+        self.split_by(x, |_, node| node.key().borrow() < key)
+    }
+
+    fn split_n(&mut self, x: u32, mut n: u32) -> (u32, u32) {
+        self.split_by(x, |map, node| {
+            let ls = map.node(node.left).size;
+            if n <= ls {
+                false
+            } else {
+                n -= ls + 1;
+                true
+            }
+        })
+    }
+
+    fn merge(&mut self, mut x: u32, mut y: u32) -> u32 {
+        let mut path = mem::take(&mut self.path);
+        let mut res = loop {
+            if x == NIL {
+                break y;
+            }
+            if y == NIL {
+                break x;
+            }
+            if self.node(x).weight < self.node(y).weight {
+                path.push((x, true));
+                x = self.node(x).right;
+            } else {
+                path.push((y, false));
+                y = self.node(y).left;
+            }
+        };
+        for (x, right) in path.drain(..).rev() {
+            if right {
+                self.node_mut(x).right = res;
+            } else {
+                self.node_mut(x).left = res;
+            }
+            self.maintain(x);
+            res = x;
+        }
+        self.path = path;
+        res
+    }
+
+    fn find<Q: Ord + ?Sized>(&self, key: &Q) -> u32
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.root;
+        while x != NIL {
+            let node = self.node(x);
+            x = match key.cmp(node.key().borrow()) {
+                Ordering::Less => node.left,
+                Ordering::Equal => return x,
+                Ordering::Greater => node.right,
+            };
+        }
+        NIL
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.find(key) {
+            NIL => None,
+            x => self.node(x).entry.as_ref().map(|it| &it.1),
+        }
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.find(key) {
+            NIL => None,
+            x => self.node_mut(x).entry.as_mut().map(|it| &mut it.1),
+        }
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key) != NIL
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(mem::replace(old, value));
+        }
+        let (l, r) = self.split_lt(self.root, &key);
+        let node = self.alloc(key, value);
+        let l = self.merge(l, node);
+        self.root = self.merge(l, r);
+        None
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        if self.find(key) == NIL {
+            return None;
+        }
+        let (l, r) = self.split_lt(self.root, key);
+        let (m, r) = self.split_n(r, 1);
+        self.root = self.merge(l, r);
+        Some(self.dealloc(m).1)
+    }
+
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> u32
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.root;
+        let mut res = 0;
+        while x != NIL {
+            let node = self.node(x);
+            if node.key().borrow() < key {
+                res += self.node(node.left).size + 1;
+                x = node.right;
+            } else {
+                x = node.left;
+            }
+        }
+        res
+    }
+
+    pub fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> u32
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.root;
+        let mut res = 0;
+        while x != NIL {
+            let node = self.node(x);
+            if node.key().borrow() <= key {
+                res += self.node(node.left).size + 1;
+                x = node.right;
+            } else {
+                x = node.left;
+            }
+        }
+        res
+    }
+
+    pub fn nth(&self, mut n: u32) -> Option<(&K, &V)> {
+        let mut x = self.root;
+        while x != NIL {
+            let node = self.node(x);
+            let ls = self.node(node.left).size;
+            match n.cmp(&ls) {
+                Ordering::Less => x = node.left,
+                Ordering::Equal => return node.entry.as_ref().map(|(k, v)| (k, v)),
+                Ordering::Greater => {
+                    n -= ls + 1;
+                    x = node.right;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, V)> {
+        if self.root == NIL {
+            return None;
+        }
+        let (l, r) = self.split_n(self.root, 1);
+        self.root = r;
+        Some(self.dealloc(l))
+    }
+
+    pub fn pop_max(&mut self) -> Option<(K, V)> {
+        let n = self.len().checked_sub(1)?;
+        let (l, r) = self.split_n(self.root, n);
+        self.root = l;
+        Some(self.dealloc(r))
+    }
+
+    pub fn iter(&self) -> ArenaIter<'_, K, V> {
+        let mut iter = ArenaIter {
+            map: self,
+            stack: Vec::new(),
+            remaining: self.len(),
+        };
+        iter.push_left(self.root);
+        iter
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for ArenaTreapMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for ArenaTreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a ArenaTreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = ArenaIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct ArenaIter<'a, K: Ord, V> {
+    map: &'a ArenaTreapMap<K, V>,
+    stack: Vec<u32>,
+    remaining: u32,
+}
+
+impl<'a, K: Ord, V> Clone for ArenaIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map,
+            stack: self.stack.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> ArenaIter<'a, K, V> {
+    fn push_left(&mut self, mut x: u32) {
+        while x != NIL {
+            self.stack.push(x);
+            x = self.map.node(x).left;
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for ArenaIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.stack.pop()?;
+        let node = self.map.node(x);
+        self.push_left(node.right);
+        self.remaining -= 1;
+        node.entry.as_ref().map(|(k, v)| (k, v))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for ArenaIter<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for ArenaIter<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for ArenaIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
@@ -0,0 +1,90 @@
+use std::{iter::Peekable, mem, vec};
+
+use crate::{NodeData, SortedBuilder, TreapMap};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchOp<K, V> {
+    Upsert(K, V),
+    Delete(K),
+}
+
+impl<K, V> BatchOp<K, V> {
+    #[inline]
+    pub fn key(&self) -> &K {
+        match self {
+            Self::Upsert(key, _) | Self::Delete(key) => key,
+        }
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    // Applies `ops`, which must be sorted by key, in one in-order walk that
+    // only enters subtrees with ops in their key range. A key given more than
+    // once ends up as its last op leaves it. Panics on ops out of order, which
+    // would otherwise leave the tree out of order; the order is checked before
+    // the walk starts, so the map is left untouched.
+    pub fn apply_batch(&mut self, ops: impl IntoIterator<Item = BatchOp<K, V>>) {
+        let ops: Vec<_> = ops.into_iter().collect();
+        assert!(
+            ops.windows(2).all(|it| it[0].key() <= it[1].key()),
+            "apply_batch ops are not sorted by key"
+        );
+        *self = Self::apply(mem::take(self), &mut ops.into_iter().peekable(), None);
+        self.check();
+    }
+
+    // Applies the ops with keys below `upper` to `x`.
+    fn apply(x: Self, ops: &mut Peekable<vec::IntoIter<BatchOp<K, V>>>, upper: Option<&K>) -> Self {
+        let below = |op: &BatchOp<K, V>| upper.is_none_or(|it| op.key() < it);
+        if !ops.peek().is_some_and(below) {
+            return x;
+        }
+        let Some(mut node) = x.into_root() else {
+            // An upsert is held back until the next op shows it isn't
+            // overridden.
+            let mut builder = SortedBuilder(Vec::new());
+            let mut pending = None;
+            while let Some(op) = ops.next_if(below) {
+                if let Some((key, value)) = pending.take_if(|it: &mut (K, V)| &it.0 != op.key()) {
+                    builder.push(NodeData::new(key, value));
+                }
+                pending = match op {
+                    BatchOp::Upsert(key, value) => Some((key, value)),
+                    BatchOp::Delete(_) => None,
+                };
+            }
+            if let Some((key, value)) = pending {
+                builder.push(NodeData::new(key, value));
+            }
+            return builder.finish();
+        };
+
+        let [left, right] = mem::take(&mut node.children);
+        let left = Self::apply(left, ops, Some(&node.key));
+        let mut keep = true;
+        while let Some(op) = ops.next_if(|op| op.key() == &node.key) {
+            keep = match op {
+                BatchOp::Upsert(_, value) => {
+                    node.value = value;
+                    true
+                }
+                BatchOp::Delete(_) => false,
+            };
+        }
+        let right = Self::apply(right, ops, upper);
+        if !keep {
+            return Self::merge(left, right);
+        }
+        // New entries may have outdrawn the node, in which case they have to
+        // be merged in above it.
+        let fits = |x: &Self| x.0.as_ref().is_none_or(|it| it.weight >= node.weight);
+        if fits(&left) && fits(&right) {
+            node.children = [left, right];
+            node.maintain();
+            node.into()
+        } else {
+            node.maintain();
+            Self::merge(Self::merge(left, node.into()), right)
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use borsh::{
+    io::{Error, ErrorKind, Read, Result, Write},
+    BorshDeserialize, BorshSerialize,
+};
+
+use crate::{len_u32, NodeData, Size, SortedBuilder, TreapMap, TreapSet};
+
+fn encoded_len(len: Size) -> Result<u32> {
+    len_u32(len).ok_or_else(|| Error::new(ErrorKind::InvalidInput, "too many entries"))
+}
+
+// Same layout as borsh's `BTreeMap`/`BTreeSet`: a `u32` length followed by the
+// entries in ascending key order. Decoding only accepts strictly ascending
+// keys, so every value has exactly one encoding.
+impl<K: Ord + BorshSerialize, V: BorshSerialize> BorshSerialize for TreapMap<K, V> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        encoded_len(self.len())?.serialize(writer)?;
+        for (key, value) in self.iter() {
+            key.serialize(writer)?;
+            value.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K: Ord + BorshDeserialize, V: BorshDeserialize> BorshDeserialize for TreapMap<K, V> {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let len = u32::deserialize_reader(reader)?;
+        let mut builder = SortedBuilder(Vec::new());
+        for _ in 0..len {
+            let key = K::deserialize_reader(reader)?;
+            let value = V::deserialize_reader(reader)?;
+            if builder.0.last().is_some_and(|it| it.key >= key) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "keys are not in strictly ascending order",
+                ));
+            }
+            builder.push(NodeData::new(key, value));
+        }
+        Ok(builder.finish())
+    }
+}
+
+impl<K: Ord + BorshSerialize> BorshSerialize for TreapSet<K> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        encoded_len(self.len())?.serialize(writer)?;
+        for key in self.iter() {
+            key.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K: Ord + BorshDeserialize> BorshDeserialize for TreapSet<K> {
+    #[inline]
+    fn deserialize_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        TreapMap::<K, ()>::deserialize_reader(reader).map(Self)
+    }
+}
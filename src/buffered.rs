@@ -0,0 +1,359 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    iter::{FusedIterator, Rev},
+    mem, slice,
+};
+
+use crate::{Iter, Size, TreapMap};
+
+// How many entries are moved from the tree into an emptied end buffer at once.
+const BATCH: usize = 32;
+
+// A `TreapMap` that keeps its smallest and largest entries in sorted buffers
+// outside the tree, so peeking at either end is O(1) and popping is O(1)
+// amortized: the tree is only split once every `BATCH` pops. Keys stay in
+// order across the parts, `front` < `map` < `back`. `front` is sorted in
+// descending order so that both ends pop off the end of a `Vec`; it is empty
+// only when the whole map is, and `back` is empty only when `map` is.
+pub struct BufferedTreapMap<K: Ord, V> {
+    front: Vec<(K, V)>,
+    map: TreapMap<K, V>,
+    back: Vec<(K, V)>,
+}
+
+enum Part {
+    Front,
+    Tree,
+    Back,
+}
+
+#[inline]
+fn entry<K, V>((key, value): &(K, V)) -> (&K, &V) {
+    (key, value)
+}
+
+fn search_front<K: Borrow<Q>, V, Q: Ord + ?Sized>(
+    front: &[(K, V)],
+    key: &Q,
+) -> Result<usize, usize> {
+    front.binary_search_by(|(k, _)| key.cmp(k.borrow()))
+}
+
+fn search_back<K: Borrow<Q>, V, Q: Ord + ?Sized>(back: &[(K, V)], key: &Q) -> Result<usize, usize> {
+    back.binary_search_by(|(k, _)| k.borrow().cmp(key))
+}
+
+impl<K: Ord, V> Default for BufferedTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for BufferedTreapMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            front: self.front.clone(),
+            map: self.map.clone(),
+            back: self.back.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for BufferedTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for BufferedTreapMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for BufferedTreapMap<K, V> {}
+
+impl<K: Ord, V> From<TreapMap<K, V>> for BufferedTreapMap<K, V> {
+    fn from(map: TreapMap<K, V>) -> Self {
+        let mut res = Self {
+            front: Vec::new(),
+            map,
+            back: Vec::new(),
+        };
+        res.refill();
+        res
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for BufferedTreapMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for BufferedTreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a BufferedTreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = BufferedIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> BufferedTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            front: Vec::new(),
+            map: TreapMap::new(),
+            back: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.front.len() as Size + self.map.len() + self.back.len() as Size
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.front.is_empty()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.map.memory_usage()
+            + (self.front.capacity() + self.back.capacity()) * mem::size_of::<(K, V)>()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn into_map(self) -> TreapMap<K, V> {
+        let front = TreapMap::from_unique_sorted_iter(self.front.into_iter().rev());
+        let back = TreapMap::from_unique_sorted_iter(self.back.into_iter());
+        TreapMap::merge(TreapMap::merge(front, self.map), back)
+    }
+
+    // Which part holds `key`, or would if it were inserted.
+    fn part<Q: Ord + ?Sized>(&self, key: &Q) -> Part
+    where
+        K: Borrow<Q>,
+    {
+        if self
+            .front
+            .first()
+            .is_none_or(|(max, _)| key <= max.borrow())
+        {
+            Part::Front
+        } else if self.map.is_empty() || key >= self.back[0].0.borrow() {
+            Part::Back
+        } else {
+            Part::Tree
+        }
+    }
+
+    // Moves all but `BATCH` entries of an overgrown buffer back into the tree.
+    fn spill(&mut self) {
+        if self.front.len() > 2 * BATCH {
+            let n = self.front.len() - BATCH;
+            let rest = TreapMap::from_unique_sorted_iter(self.front.drain(..n).rev());
+            self.map = TreapMap::merge(rest, mem::take(&mut self.map));
+        }
+        if self.back.len() > 2 * BATCH {
+            let n = self.back.len() - BATCH;
+            let rest = TreapMap::from_unique_sorted_iter(self.back.drain(..n));
+            self.map = TreapMap::merge(mem::take(&mut self.map), rest);
+        }
+    }
+
+    // Restores the buffers after removals, taking entries from the tree.
+    fn refill(&mut self) {
+        if self.front.is_empty() {
+            if self.map.is_empty() {
+                self.front.extend(self.back.drain(..).rev());
+            } else {
+                let (l, r) = mem::take(&mut self.map).split_n(BATCH as Size);
+                self.map = r;
+                self.front.extend(l.into_iter().rev());
+            }
+        }
+        if self.back.is_empty() && !self.map.is_empty() {
+            let n = self.map.len().saturating_sub(BATCH as Size);
+            let (l, r) = mem::take(&mut self.map).split_n(n);
+            self.map = l;
+            self.back.extend(r);
+        }
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.part(key) {
+            Part::Front => Some(&self.front[search_front(&self.front, key).ok()?].1),
+            Part::Tree => self.map.get(key),
+            Part::Back => Some(&self.back[search_back(&self.back, key).ok()?].1),
+        }
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.part(key) {
+            Part::Front => {
+                let i = search_front(&self.front, key).ok()?;
+                Some(&mut self.front[i].1)
+            }
+            Part::Tree => self.map.get_mut(key),
+            Part::Back => {
+                let i = search_back(&self.back, key).ok()?;
+                Some(&mut self.back[i].1)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.part(&key) {
+            Part::Front => match search_front(&self.front, &key) {
+                Ok(i) => return Some(mem::replace(&mut self.front[i].1, value)),
+                Err(i) => self.front.insert(i, (key, value)),
+            },
+            Part::Tree => return self.map.insert(key, value),
+            Part::Back => match search_back(&self.back, &key) {
+                Ok(i) => return Some(mem::replace(&mut self.back[i].1, value)),
+                Err(i) => self.back.insert(i, (key, value)),
+            },
+        }
+        self.spill();
+        self.refill();
+        None
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let res = match self.part(key) {
+            Part::Front => self.front.remove(search_front(&self.front, key).ok()?).1,
+            Part::Tree => self.map.remove(key)?,
+            Part::Back => self.back.remove(search_back(&self.back, key).ok()?).1,
+        };
+        self.refill();
+        Some(res)
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, V)> {
+        let res = self.front.pop();
+        self.refill();
+        res
+    }
+
+    pub fn pop_max(&mut self) -> Option<(K, V)> {
+        let res = match self.back.pop() {
+            Some(it) => it,
+            None if self.front.is_empty() => return None,
+            None => self.front.remove(0),
+        };
+        self.refill();
+        Some(res)
+    }
+
+    #[inline]
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.front.last().map(entry)
+    }
+
+    #[inline]
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.back.last().or(self.front.first()).map(entry)
+    }
+
+    pub fn iter(&self) -> BufferedIter<'_, K, V> {
+        BufferedIter {
+            front: self.front.iter().rev(),
+            map: self.map.iter(),
+            back: self.back.iter(),
+        }
+    }
+}
+
+pub struct BufferedIter<'a, K: Ord, V> {
+    front: Rev<slice::Iter<'a, (K, V)>>,
+    map: Iter<'a, K, V>,
+    back: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K: Ord, V> Clone for BufferedIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            front: self.front.clone(),
+            map: self.map.clone(),
+            back: self.back.clone(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for BufferedIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.front.next() {
+            Some(it) => Some(entry(it)),
+            None => self.map.next().or_else(|| self.back.next().map(entry)),
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.map.len() + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for BufferedIter<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.back.next_back() {
+            Some(it) => Some(entry(it)),
+            None => self
+                .map
+                .next_back()
+                .or_else(|| self.front.next_back().map(entry)),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for BufferedIter<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for BufferedIter<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for BufferedIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
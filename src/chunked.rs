@@ -0,0 +1,471 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+    mem, slice,
+};
+
+use crate::{rng, Size, Weight};
+
+// Upper bound on the entries of one chunk; a chunk that grows past it is cut
+// in half.
+const CHUNK: usize = 64;
+
+// A treap node holding a sorted, non-empty run of entries. Keys and values are
+// kept apart so that searching a chunk only touches keys.
+struct Chunk<K, V> {
+    left: Tree<K, V>,
+    right: Tree<K, V>,
+    size: Size,
+    keys: Vec<K>,
+    values: Vec<V>,
+
+    weight: Weight,
+}
+
+type Tree<K, V> = Option<Box<Chunk<K, V>>>;
+
+#[inline]
+fn size<K, V>(tree: &Tree<K, V>) -> Size {
+    tree.as_ref().map_or(0, |it| it.size)
+}
+
+impl<K, V> Chunk<K, V> {
+    fn new(keys: Vec<K>, values: Vec<V>, weight: Weight) -> Box<Self> {
+        Box::new(Self {
+            left: None,
+            right: None,
+            size: keys.len() as Size,
+            keys,
+            values,
+
+            weight,
+        })
+    }
+
+    #[inline]
+    fn maintain(&mut self) {
+        self.size = size(&self.left) + size(&self.right) + self.keys.len() as Size;
+    }
+}
+
+// Keys are ordered between chunks as well as within them, so `split_lt` only
+// ever cuts the chunk the key falls into and `merge` just links chunks.
+fn split_lt<K: Borrow<Q>, V, Q: Ord + ?Sized>(
+    tree: Tree<K, V>,
+    key: &Q,
+) -> (Tree<K, V>, Tree<K, V>) {
+    let Some(mut node) = tree else {
+        return (None, None);
+    };
+    if key <= node.keys[0].borrow() {
+        let (l, r) = split_lt(node.left.take(), key);
+        node.left = r;
+        node.maintain();
+        (l, Some(node))
+    } else if node.keys.last().unwrap().borrow() < key {
+        let (l, r) = split_lt(node.right.take(), key);
+        node.right = l;
+        node.maintain();
+        (Some(node), r)
+    } else {
+        let i = node.keys.partition_point(|it| it.borrow() < key);
+        let mut rest = Chunk::new(
+            node.keys.split_off(i),
+            node.values.split_off(i),
+            node.weight,
+        );
+        rest.right = node.right.take();
+        rest.maintain();
+        node.maintain();
+        (Some(node), Some(rest))
+    }
+}
+
+fn merge<K, V>(x: Tree<K, V>, y: Tree<K, V>) -> Tree<K, V> {
+    match (x, y) {
+        (None, y) => y,
+        (x, None) => x,
+        (Some(mut x), Some(mut y)) => {
+            if x.weight < y.weight {
+                x.right = merge(x.right.take(), Some(y));
+                x.maintain();
+                Some(x)
+            } else {
+                y.left = merge(Some(x), y.left.take());
+                y.maintain();
+                Some(y)
+            }
+        }
+    }
+}
+
+// A treap over chunks of up to `CHUNK` sorted entries, trading the per-entry
+// nodes of `TreapMap` for B-tree-like locality: lookups binary-search a
+// contiguous run of keys once they reach the right chunk. Chunks are only
+// dropped once emptied, so heavy removal can leave them sparse.
+pub struct ChunkedTreapMap<K: Ord, V>(Tree<K, V>);
+
+impl<K: Ord, V> Default for ChunkedTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for ChunkedTreapMap<K, V> {
+    fn clone(&self) -> Self {
+        fn clone_tree<K: Clone, V: Clone>(tree: &Tree<K, V>) -> Tree<K, V> {
+            tree.as_ref().map(|node| {
+                Box::new(Chunk {
+                    left: clone_tree(&node.left),
+                    right: clone_tree(&node.right),
+                    size: node.size,
+                    keys: node.keys.clone(),
+                    values: node.values.clone(),
+
+                    weight: node.weight,
+                })
+            })
+        }
+        Self(clone_tree(&self.0))
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for ChunkedTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for ChunkedTreapMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for ChunkedTreapMap<K, V> {}
+
+impl<K: Ord, V> FromIterator<(K, V)> for ChunkedTreapMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for ChunkedTreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a ChunkedTreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = ChunkedIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> ChunkedTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(None)
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        size(&self.0)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.0 = None;
+    }
+
+    // Walks the chunks, counting each one's key and value buffers at capacity.
+    pub fn memory_usage(&self) -> usize {
+        let mut res = 0;
+        let mut stack: Vec<_> = self.0.iter().collect();
+        while let Some(node) = stack.pop() {
+            res += mem::size_of::<Chunk<K, V>>()
+                + node.keys.capacity() * mem::size_of::<K>()
+                + node.values.capacity() * mem::size_of::<V>();
+            stack.extend(&node.left);
+            stack.extend(&node.right);
+        }
+        res
+    }
+
+    pub fn split_lt<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = split_lt(self.0, key);
+        (Self(l), Self(r))
+    }
+
+    // Every key of `x` must be less than every key of `y`.
+    #[inline]
+    pub fn merge(x: Self, y: Self) -> Self {
+        Self(merge(x.0, y.0))
+    }
+
+    // The chunk `key` would be stored in, if it is present at all.
+    fn find<Q: Ord + ?Sized>(&self, key: &Q) -> Option<(&Chunk<K, V>, usize)>
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = &self.0;
+        while let Some(node) = x {
+            if key < node.keys[0].borrow() {
+                x = &node.left;
+            } else if node.keys.last().unwrap().borrow() < key {
+                x = &node.right;
+            } else {
+                let i = node.keys.binary_search_by(|it| it.borrow().cmp(key)).ok()?;
+                return Some((node, i));
+            }
+        }
+        None
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key).map(|(node, i)| &node.values[i])
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = &mut self.0;
+        while let Some(node) = x {
+            if key < node.keys[0].borrow() {
+                x = &mut node.left;
+            } else if node.keys.last().unwrap().borrow() < key {
+                x = &mut node.right;
+            } else {
+                let i = node.keys.binary_search_by(|it| it.borrow().cmp(key)).ok()?;
+                return Some(&mut node.values[i]);
+            }
+        }
+        None
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(mem::replace(old, value));
+        }
+        let Some(mut node) = self.0.as_mut() else {
+            self.0 = Some(Chunk::new(vec![key], vec![value], rng::random_weight()));
+            return None;
+        };
+        // Only steps into children that exist; past either end of a chunk
+        // with no child there, the entry joins that chunk.
+        loop {
+            node.size += 1;
+            if key < node.keys[0] && node.left.is_some() {
+                node = node.left.as_mut().unwrap();
+            } else if *node.keys.last().unwrap() < key && node.right.is_some() {
+                node = node.right.as_mut().unwrap();
+            } else {
+                break;
+            }
+        }
+        let i = node.keys.partition_point(|it| it < &key);
+        node.keys.insert(i, key);
+        node.values.insert(i, value);
+        if node.keys.len() > CHUNK {
+            // The upper half becomes a chunk of its own, with a fresh weight
+            // and linked in from the root. Every node whose size went stale
+            // lies on that split's path.
+            let half = node.keys.len() / 2;
+            let rest = Chunk::new(
+                node.keys.split_off(half),
+                node.values.split_off(half),
+                rng::random_weight(),
+            );
+            let (l, r) = split_lt(self.0.take(), &rest.keys[0]);
+            self.0 = merge(merge(l, Some(rest)), r);
+        }
+        None
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key)?;
+        let mut x = &mut self.0;
+        loop {
+            let node = x.as_mut().unwrap();
+            node.size -= 1;
+            if key < node.keys[0].borrow() {
+                x = &mut x.as_mut().unwrap().left;
+            } else if node.keys.last().unwrap().borrow() < key {
+                x = &mut x.as_mut().unwrap().right;
+            } else {
+                break;
+            }
+        }
+        let node = x.as_mut().unwrap();
+        let i = node
+            .keys
+            .binary_search_by(|it| it.borrow().cmp(key))
+            .unwrap();
+        node.keys.remove(i);
+        let res = node.values.remove(i);
+        if node.keys.is_empty() {
+            let node = x.take().unwrap();
+            *x = merge(node.left, node.right);
+        }
+        Some(res)
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut x = self.0.as_ref()?;
+        while let Some(y) = &x.left {
+            x = y;
+        }
+        Some((&x.keys[0], &x.values[0]))
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut x = self.0.as_ref()?;
+        while let Some(y) = &x.right {
+            x = y;
+        }
+        Some((x.keys.last().unwrap(), x.values.last().unwrap()))
+    }
+
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = &self.0;
+        let mut res = 0;
+        while let Some(node) = x {
+            if key <= node.keys[0].borrow() {
+                x = &node.left;
+                continue;
+            }
+            res += size(&node.left);
+            let i = node.keys.partition_point(|it| it.borrow() < key);
+            res += i as Size;
+            if i < node.keys.len() {
+                break;
+            }
+            x = &node.right;
+        }
+        res
+    }
+
+    pub fn nth(&self, mut n: Size) -> Option<(&K, &V)> {
+        let mut x = &self.0;
+        while let Some(node) = x {
+            let ls = size(&node.left);
+            if n < ls {
+                x = &node.left;
+                continue;
+            }
+            n -= ls;
+            if let Some(key) = node.keys.get(n as usize) {
+                return Some((key, &node.values[n as usize]));
+            }
+            n -= node.keys.len() as Size;
+            x = &node.right;
+        }
+        None
+    }
+
+    pub fn iter(&self) -> ChunkedIter<'_, K, V> {
+        let mut iter = ChunkedIter {
+            stack: Vec::new(),
+            keys: [].iter(),
+            values: [].iter(),
+            remaining: self.len(),
+        };
+        iter.push_left(&self.0);
+        iter
+    }
+}
+
+pub struct ChunkedIter<'a, K, V> {
+    stack: Vec<&'a Chunk<K, V>>,
+    keys: slice::Iter<'a, K>,
+    values: slice::Iter<'a, V>,
+    remaining: Size,
+}
+
+impl<'a, K, V> Clone for ChunkedIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            stack: self.stack.clone(),
+            keys: self.keys.clone(),
+            values: self.values.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<'a, K, V> ChunkedIter<'a, K, V> {
+    fn push_left(&mut self, mut x: &'a Tree<K, V>) {
+        while let Some(node) = x {
+            self.stack.push(node);
+            x = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for ChunkedIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(key) = self.keys.next() {
+                self.remaining -= 1;
+                return Some((key, self.values.next().unwrap()));
+            }
+            let node = self.stack.pop()?;
+            self.keys = node.keys.iter();
+            self.values = node.values.iter();
+            self.push_left(&node.right);
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for ChunkedIter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for ChunkedIter<'a, K, V> {}
+
+impl<'a, K: Debug, V: Debug> Debug for ChunkedIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{TreapMap, TreapSet};
+
+impl<K: Ord, V> From<BTreeMap<K, V>> for TreapMap<K, V> {
+    #[inline]
+    fn from(map: BTreeMap<K, V>) -> Self {
+        Self::from_unique_sorted_iter(map.into_iter())
+    }
+}
+
+impl<K: Ord, V> From<TreapMap<K, V>> for BTreeMap<K, V> {
+    #[inline]
+    fn from(map: TreapMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: Ord, V, S> From<HashMap<K, V, S>> for TreapMap<K, V> {
+    fn from(map: HashMap<K, V, S>) -> Self {
+        let mut vec: Vec<(K, V)> = map.into_iter().collect();
+        vec.sort_unstable_by(|x, y| x.0.cmp(&y.0));
+        Self::from_unique_sorted_iter(vec.into_iter())
+    }
+}
+
+impl<K: Ord> From<BTreeSet<K>> for TreapSet<K> {
+    #[inline]
+    fn from(set: BTreeSet<K>) -> Self {
+        Self::from_unique_sorted_iter(set.into_iter())
+    }
+}
+
+impl<K: Ord> From<TreapSet<K>> for BTreeSet<K> {
+    #[inline]
+    fn from(set: TreapSet<K>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+impl<K: Ord, S> From<HashSet<K, S>> for TreapSet<K> {
+    fn from(set: HashSet<K, S>) -> Self {
+        let mut vec: Vec<K> = set.into_iter().collect();
+        vec.sort_unstable();
+        Self::from_unique_sorted_iter(vec.into_iter())
+    }
+}
+
+impl<K: Ord + Hash, S: BuildHasher + Default> From<TreapSet<K>> for HashSet<K, S> {
+    #[inline]
+    fn from(set: TreapSet<K>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq<BTreeMap<K, V>> for TreapMap<K, V> {
+    fn eq(&self, other: &BTreeMap<K, V>) -> bool {
+        self.len() as usize == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq<&[(K, V)]> for TreapMap<K, V> {
+    fn eq(&self, other: &&[(K, V)]) -> bool {
+        self.len() as usize == other.len() && self.iter().eq(other.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+impl<K: Ord> PartialEq<BTreeSet<K>> for TreapSet<K> {
+    fn eq(&self, other: &BTreeSet<K>) -> bool {
+        self.len() as usize == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord> PartialEq<&[K]> for TreapSet<K> {
+    fn eq(&self, other: &&[K]) -> bool {
+        self.len() as usize == other.len() && self.iter().eq(other.iter())
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    borrow::Borrow,
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fmt::{self, Debug},
+    hash::{Hash, Hasher},
+    iter::FusedIterator,
+};
+
+use crate::{
+    multiset::{Counts, Tally},
+    Iter, Size,
+};
+
+// Counts share `TreapMultiSet`'s tree, whose subtree sums make the weighted
+// rank queries O(log n).
+#[derive(Clone)]
+pub struct TreapCounter<K: Ord> {
+    counts: Counts<K, u64>,
+}
+impl<K: Ord> Default for TreapCounter<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord + Debug> Debug for TreapCounter<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<K: Ord> PartialEq for TreapCounter<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+impl<K: Ord> Eq for TreapCounter<K> {}
+impl<K: Ord + Hash> Hash for TreapCounter<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for entry in self.iter() {
+            entry.hash(state);
+        }
+    }
+}
+
+impl<K: Ord> TreapCounter<K> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            counts: Counts::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.counts.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.counts.memory_usage()
+    }
+
+    #[inline]
+    pub fn total(&self) -> u64 {
+        self.counts.total()
+    }
+
+    #[inline]
+    pub fn increment(&mut self, key: K) -> u64 {
+        self.add(key, 1)
+    }
+
+    #[inline]
+    pub fn add(&mut self, key: K, n: u64) -> u64 {
+        self.counts.insert_n(key, n)
+    }
+
+    #[inline]
+    pub fn decrement_saturating<Q: Ord + ?Sized>(&mut self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.sub_saturating(key, 1)
+    }
+
+    pub fn sub_saturating<Q: Ord + ?Sized>(&mut self, key: &Q, n: u64) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        let count = self.counts.count(key);
+        if count == 0 {
+            return 0;
+        }
+        count - self.counts.remove_n(key, n)
+    }
+
+    #[inline]
+    pub fn count<Q: Ord + ?Sized>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.counts.count(key)
+    }
+
+    #[inline]
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.counts.remove_n(key, u64::MAX)
+    }
+
+    pub fn most_common(&self, k: usize) -> Vec<(&K, u64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        // Among equal counts the smaller key wins, as it comes first.
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (key, &count) in self.iter() {
+            heap.push(Reverse((count, Reverse(key))));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((count, Reverse(key)))| (key, count))
+            .collect()
+    }
+
+    #[inline]
+    pub fn weighted_num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.counts.num_lt(key)
+    }
+
+    #[inline]
+    pub fn weighted_num_le<Q: Ord + ?Sized>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.counts.num_le(key)
+    }
+
+    #[inline]
+    pub fn weighted_nth(&self, n: u64) -> Option<&K> {
+        self.counts.nth(n)
+    }
+
+    #[inline]
+    pub fn iter(&self) -> CounterIter<'_, K> {
+        CounterIter(self.counts.iter())
+    }
+}
+
+impl<'a, K: Ord> IntoIterator for &'a TreapCounter<K> {
+    type Item = (&'a K, &'a u64);
+    type IntoIter = CounterIter<'a, K>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord> FromIterator<K> for TreapCounter<K> {
+    fn from_iter<T: IntoIterator<Item = K>>(iter: T) -> Self {
+        let mut res = Self::new();
+        for key in iter {
+            res.increment(key);
+        }
+        res
+    }
+}
+
+pub struct CounterIter<'a, K: Ord>(Iter<'a, K, Tally<u64>>);
+
+impl<'a, K: Ord> Clone for CounterIter<'a, K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<'a, K: Ord> Iterator for CounterIter<'a, K> {
+    type Item = (&'a K, &'a u64);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, tally)| (key, &tally.count))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Ord> ExactSizeIterator for CounterIter<'a, K> {}
+
+impl<'a, K: Ord> FusedIterator for CounterIter<'a, K> {}
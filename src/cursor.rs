@@ -0,0 +1,520 @@
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt::{self, Debug},
+    mem,
+    ops::Bound,
+    ptr::{self, NonNull},
+};
+
+use crate::{Iter, NodeData, Path, Size, TreapMap, LEFT, RIGHT};
+
+// A cursor points either at an entry or at the "ghost" position past the last
+// one, whose rank is `len`. Moving off either end lands on the ghost and
+// moving off the ghost wraps around, like `LinkedList`'s cursors.
+pub struct Cursor<'a, K: Ord, V> {
+    map: &'a TreapMap<K, V>,
+    path: Path<'a, K, V>,
+    rank: Size,
+}
+
+impl<'a, K: Ord, V> Clone for Cursor<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map,
+            path: self.path.clone(),
+            rank: self.rank,
+        }
+    }
+}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for Cursor<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cursor")
+            .field("rank", &self.rank)
+            .field("current", &self.peek())
+            .finish()
+    }
+}
+
+impl<'a, K: Ord, V> Cursor<'a, K, V> {
+    #[inline]
+    pub fn rank(&self) -> Size {
+        self.rank
+    }
+
+    #[inline]
+    pub fn peek(&self) -> Option<(&'a K, &'a V)> {
+        self.path.last().map(|it| (&it.key, &it.value))
+    }
+
+    pub fn seek<Q: Ord + ?Sized>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        (self.path, self.rank) = self.map.lower_path(key);
+    }
+
+    // Like `seek`, but climbs from the current entry only as far as the
+    // subtree that must hold the result before descending again, so a key d
+    // entries away takes O(log d) steps instead of O(log n). From the ghost
+    // position this is a plain `seek`.
+    pub fn seek_near<Q: Ord + ?Sized>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        let Some(&last) = self.path.last() else {
+            return self.seek(key);
+        };
+        // The result lies in the subtree of `path[j]` once the nearest
+        // ancestors bounding it from below and above are known to admit `key`.
+        // `base` counts the entries before the subtree of `path[i]`.
+        let mut j = self.path.len() - 1;
+        let mut base = self.rank - last.children[LEFT].len();
+        let mut start = base;
+        let (mut lo, mut hi) = (false, None);
+        let mut i = j;
+        while i > 0 && !(lo && hi.is_some()) {
+            let (parent, child) = (self.path[i - 1], self.path[i]);
+            i -= 1;
+            if parent.children[RIGHT]
+                .0
+                .as_deref()
+                .is_some_and(|it| ptr::eq(it, child))
+            {
+                base -= parent.children[LEFT].len() + 1;
+                if lo {
+                    continue;
+                }
+                count!(comparisons);
+                if parent.key.borrow() < key {
+                    lo = true;
+                    continue;
+                }
+            } else {
+                if hi.is_some() {
+                    continue;
+                }
+                count!(comparisons);
+                if key <= parent.key.borrow() {
+                    hi = Some(i);
+                    continue;
+                }
+            }
+            (j, start, lo, hi) = (i, base, false, None);
+        }
+
+        let root = self.path[j];
+        self.path.truncate(j);
+        // Without an entry not less than `key` in the subtree, the result is
+        // the ancestor bounding it from above, or the ghost if there is none.
+        let mut found = hi.map_or(0, |it| it + 1);
+        let mut x = Some(root);
+        while let Some(node) = x {
+            self.path.push(node);
+            count!(comparisons);
+            if node.key.borrow() < key {
+                start += node.children[LEFT].len() + 1;
+                x = node.children[RIGHT].0.as_deref();
+            } else {
+                found = self.path.len();
+                x = node.children[LEFT].0.as_deref();
+            }
+        }
+        self.path.truncate(found);
+        self.rank = start;
+    }
+
+    pub fn seek_nth(&mut self, n: Size) {
+        self.path.clear();
+        self.rank = n.min(self.map.len());
+        if let Some(root) = self.map.0.as_deref() {
+            if self.rank < root.size {
+                root.push_path(self.rank, &mut self.path);
+            }
+        }
+    }
+
+    // Not an `Iterator`: the cursor wraps around instead of ending.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.path.is_empty() {
+            self.seek_nth(0);
+        } else {
+            Iter::step(&mut self.path, RIGHT);
+            self.rank += 1;
+        }
+        self.peek()
+    }
+
+    pub fn prev(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.path.is_empty() {
+            self.seek_nth(self.rank.wrapping_sub(1));
+        } else if self.rank == 0 {
+            self.path.clear();
+            self.rank = self.map.len();
+        } else {
+            Iter::step(&mut self.path, LEFT);
+            self.rank -= 1;
+        }
+        self.peek()
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    pub fn cursor(&self) -> Cursor<'_, K, V> {
+        let mut cursor = Cursor {
+            map: self,
+            path: Path::new(),
+            rank: 0,
+        };
+        cursor.seek_nth(0);
+        cursor
+    }
+
+    // Rank of the first entry above `bound`, or `len` if there is none.
+    fn lower_rank<Q: Ord + ?Sized>(&self, bound: Bound<&Q>) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        match bound {
+            Bound::Included(key) => self.num_lt(key),
+            Bound::Excluded(key) => self.num_le(key),
+            Bound::Unbounded => 0,
+        }
+    }
+
+    // Rank of the last entry below `bound`, or `len` if there is none.
+    fn upper_rank<Q: Ord + ?Sized>(&self, bound: Bound<&Q>) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        let num = match bound {
+            Bound::Included(key) => self.num_le(key),
+            Bound::Excluded(key) => self.num_lt(key),
+            Bound::Unbounded => self.len(),
+        };
+        num.checked_sub(1).unwrap_or(self.len())
+    }
+
+    // The cursor lands on the first entry above `bound`, or on the ghost
+    // position if there is none.
+    pub fn lower_bound<Q: Ord + ?Sized>(&self, bound: Bound<&Q>) -> Cursor<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let mut cursor = self.cursor();
+        cursor.seek_nth(self.lower_rank(bound));
+        cursor
+    }
+
+    // The cursor lands on the last entry below `bound`, or on the ghost
+    // position if there is none.
+    pub fn upper_bound<Q: Ord + ?Sized>(&self, bound: Bound<&Q>) -> Cursor<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let mut cursor = self.cursor();
+        cursor.seek_nth(self.upper_rank(bound));
+        cursor
+    }
+
+    // Looks `key` up by `Cursor::seek_near` from `hint`, which is left on the
+    // first entry not less than `key` so that the next nearby lookup starts
+    // there.
+    pub fn get_with_hint<'a, Q: Ord + ?Sized>(
+        &'a self,
+        hint: &mut Cursor<'a, K, V>,
+        key: &Q,
+    ) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+    {
+        assert!(ptr::eq(hint.map, self), "hint is a cursor into another map");
+        hint.seek_near(key);
+        hint.peek().filter(|it| it.0.borrow() == key).map(|it| it.1)
+    }
+}
+
+// Positions work as for `Cursor`. The tree can't stay borrowed while the
+// cursor edits it, so the root path to the current entry is kept as pointers
+// instead, built on demand and dropped whenever the tree is restructured.
+// Steps along it take amortised O(1), as with `Cursor`.
+pub struct CursorMut<'a, K: Ord, V> {
+    map: &'a mut TreapMap<K, V>,
+    // Either empty or the root path to the entry at `rank`. The cursor borrows
+    // the map uniquely and clears this before splitting or merging anything,
+    // so the nodes stay where the pointers say.
+    path: Vec<NonNull<NodeData<K, V>>>,
+    rank: Size,
+}
+
+// SAFETY: `path` only points into `map`, so the cursor is as thread-safe as
+// the `&mut TreapMap` it holds.
+unsafe impl<'a, K: Ord + Send, V: Send> Send for CursorMut<'a, K, V> {}
+unsafe impl<'a, K: Ord + Sync, V: Sync> Sync for CursorMut<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for CursorMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CursorMut")
+            .field("rank", &self.rank)
+            .field("current", &self.peek())
+            .finish()
+    }
+}
+
+impl<'a, K: Ord, V> CursorMut<'a, K, V> {
+    #[inline]
+    pub fn rank(&self) -> Size {
+        self.rank
+    }
+
+    pub fn peek(&self) -> Option<(&K, &V)> {
+        match self.path.last() {
+            Some(node) => {
+                // SAFETY: see `path`.
+                let node = unsafe { node.as_ref() };
+                Some((&node.key, &node.value))
+            }
+            None => self.map.nth_kv(self.rank),
+        }
+    }
+
+    pub fn peek_mut(&mut self) -> Option<(&K, &mut V)> {
+        self.fill_path();
+        let mut node = *self.path.last()?;
+        // SAFETY: see `path`; the node stays borrowed through `self`.
+        let node = unsafe { node.as_mut() };
+        Some((&node.key, &mut node.value))
+    }
+
+    #[inline]
+    pub fn seek<Q: Ord + ?Sized>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        self.path.clear();
+        self.rank = self.map.num_lt(key);
+    }
+
+    #[inline]
+    pub fn seek_nth(&mut self, n: Size) {
+        self.path.clear();
+        self.rank = n.min(self.map.len());
+    }
+
+    // Not an `Iterator`: the cursor wraps around instead of ending.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&K, &mut V)> {
+        if self.rank == self.map.len() {
+            self.rank = 0;
+        } else {
+            self.fill_path();
+            self.step(RIGHT);
+            self.rank += 1;
+        }
+        self.peek_mut()
+    }
+
+    pub fn prev(&mut self) -> Option<(&K, &mut V)> {
+        if self.rank == 0 {
+            self.path.clear();
+            self.rank = self.map.len();
+        } else if self.rank == self.map.len() {
+            self.rank -= 1;
+        } else {
+            self.fill_path();
+            self.step(LEFT);
+            self.rank -= 1;
+        }
+        self.peek_mut()
+    }
+
+    // Descends to the entry at `rank` unless `path` already leads there or the
+    // cursor is on the ghost.
+    fn fill_path(&mut self) {
+        if !self.path.is_empty() || self.rank == self.map.len() {
+            return;
+        }
+        let mut n = self.rank;
+        let mut node = NonNull::from(self.map.0.as_deref_mut().unwrap());
+        loop {
+            self.path.push(node);
+            // SAFETY: see `path`.
+            let x = unsafe { node.as_mut() };
+            let ls = x.children[LEFT].len();
+            let dir = match n.cmp(&ls) {
+                Ordering::Less => LEFT,
+                Ordering::Equal => return,
+                Ordering::Greater => {
+                    n -= ls + 1;
+                    RIGHT
+                }
+            };
+            node = NonNull::from(x.children[dir].0.as_deref_mut().unwrap());
+        }
+    }
+
+    // `Iter::step` over `path`, which is left empty past either end.
+    fn step(&mut self, dir: usize) {
+        let Some(mut last) = self.path.pop() else {
+            return;
+        };
+        // SAFETY: see `path`.
+        if let Some(node) = unsafe { last.as_mut() }.children[dir].0.as_deref_mut() {
+            self.path.push(last);
+            let mut node = NonNull::from(node);
+            loop {
+                self.path.push(node);
+                node = match unsafe { node.as_mut() }.children[1 - dir].0.as_deref_mut() {
+                    Some(x) => NonNull::from(x),
+                    None => return,
+                }
+            }
+        }
+        while let Some(parent) = self.path.pop() {
+            if unsafe { parent.as_ref() }.children[dir]
+                .0
+                .as_deref()
+                .is_some_and(|it| ptr::eq(it, last.as_ptr()))
+            {
+                last = parent;
+            } else {
+                self.path.push(parent);
+                return;
+            }
+        }
+    }
+
+    fn insert_at(&mut self, rank: Size, key: K, value: V) -> Result<(), (K, V)> {
+        let fits = rank
+            .checked_sub(1)
+            .is_none_or(|it| self.map.nth_kv(it).unwrap().0 < &key)
+            && self.map.nth_kv(rank).is_none_or(|it| &key < it.0);
+        if !fits {
+            return Err((key, value));
+        }
+        self.insert_unchecked(rank, key, value);
+        Ok(())
+    }
+
+    fn insert_unchecked(&mut self, rank: Size, key: K, value: V) {
+        self.path.clear();
+        let (l, r) = mem::take(self.map).split_n(rank);
+        *self.map = TreapMap::merge(TreapMap::merge(l, NodeData::new(key, value).into()), r);
+        self.map.check();
+    }
+
+    // Where `key` is or would be inserted, as for `binary_search`. The slots
+    // on either side of the cursor are tried before searching from the root.
+    fn locate(&self, key: &K) -> Result<Size, Size> {
+        let cmp = |rank: Size| self.map.nth_kv(rank).map(|it| it.0.cmp(key));
+        match cmp(self.rank) {
+            Some(Ordering::Equal) => return Ok(self.rank),
+            Some(Ordering::Less) => match cmp(self.rank + 1) {
+                None | Some(Ordering::Greater) => return Err(self.rank + 1),
+                Some(Ordering::Equal) => return Ok(self.rank + 1),
+                Some(Ordering::Less) => {}
+            },
+            _ => match self.rank.checked_sub(1).and_then(cmp) {
+                None | Some(Ordering::Less) => return Err(self.rank),
+                Some(Ordering::Equal) => return Ok(self.rank - 1),
+                Some(Ordering::Greater) => {}
+            },
+        }
+        let rank = self.map.num_lt(key);
+        match cmp(rank) {
+            Some(Ordering::Equal) => Ok(rank),
+            _ => Err(rank),
+        }
+    }
+
+    // Inserts like `TreapMap::insert` and leaves the cursor on the entry. A key
+    // that sorts right next to the cursor, as in ascending runs, costs two
+    // comparisons instead of a search, though the tree is still split and
+    // merged at O(log n).
+    pub fn insert_hint(&mut self, key: K, value: V) -> Option<V> {
+        match self.locate(&key) {
+            Ok(rank) => {
+                if rank != self.rank {
+                    self.path.clear();
+                    self.rank = rank;
+                }
+                Some(mem::replace(self.map.nth_kv_mut(rank).unwrap().1, value))
+            }
+            Err(rank) => {
+                self.insert_unchecked(rank, key, value);
+                self.rank = rank;
+                None
+            }
+        }
+    }
+
+    // The key must sort strictly between the neighbours of the new entry,
+    // otherwise it is handed back. The cursor stays on the current entry.
+    pub fn insert_before(&mut self, key: K, value: V) -> Result<(), (K, V)> {
+        self.insert_at(self.rank, key, value)?;
+        self.rank += 1;
+        Ok(())
+    }
+
+    // On the ghost position this inserts at the front, as that is what
+    // follows the ghost.
+    pub fn insert_after(&mut self, key: K, value: V) -> Result<(), (K, V)> {
+        if self.rank == self.map.len() {
+            self.insert_at(0, key, value)?;
+            self.rank += 1;
+            Ok(())
+        } else {
+            self.insert_at(self.rank + 1, key, value)
+        }
+    }
+
+    // The cursor moves on to the entry after the removed one.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        if self.rank == self.map.len() {
+            return None;
+        }
+        self.path.clear();
+        let (l, r) = mem::take(self.map).split_n(self.rank);
+        let (m, r) = r.split_n(1);
+        *self.map = TreapMap::merge(l, r);
+        let node = m.into_root().unwrap();
+        Some((node.key, node.value))
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    #[inline]
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, K, V> {
+        CursorMut {
+            map: self,
+            path: Vec::new(),
+            rank: 0,
+        }
+    }
+
+    pub fn lower_bound_mut<Q: Ord + ?Sized>(&mut self, bound: Bound<&Q>) -> CursorMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let rank = self.lower_rank(bound);
+        CursorMut {
+            map: self,
+            path: Vec::new(),
+            rank,
+        }
+    }
+
+    pub fn upper_bound_mut<Q: Ord + ?Sized>(&mut self, bound: Bound<&Q>) -> CursorMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let rank = self.upper_rank(bound);
+        CursorMut {
+            map: self,
+            path: Vec::new(),
+            rank,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{atomic::Ordering, Arc},
+};
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned};
+
+use crate::PersistentTreapMap;
+
+struct Published<K: Ord, V> {
+    root: Atomic<PersistentTreapMap<K, V>>,
+}
+
+impl<K: Ord, V> Drop for Published<K, V> {
+    fn drop(&mut self) {
+        // SAFETY: readers borrow the `Arc` this lives in while they are
+        // pinned, so none are left, and `root` is never null.
+        unsafe {
+            drop(
+                self.root
+                    .load(Ordering::Relaxed, epoch::unprotected())
+                    .into_owned(),
+            )
+        }
+    }
+}
+
+// A map with a single writer and any number of lock-free readers. The writer
+// changes a private `PersistentTreapMap` and `publish`es it, which swaps in an
+// O(1) clone as the version readers see. Readers pin the current version
+// with `EpochReader::read`, and a version replaced while they hold it is only
+// dropped once every reader pinned before the swap is done. As versions share
+// their nodes, the writer's changes never touch nodes that readers can reach;
+// they copy them instead.
+pub struct EpochTreapMap<K: Ord, V> {
+    map: PersistentTreapMap<K, V>,
+    published: Arc<Published<K, V>>,
+}
+
+impl<K: Ord + Send + Sync + 'static, V: Send + Sync + 'static> Default for EpochTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for EpochTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+impl<K: Ord + Send + Sync + 'static, V: Send + Sync + 'static> From<PersistentTreapMap<K, V>>
+    for EpochTreapMap<K, V>
+{
+    fn from(map: PersistentTreapMap<K, V>) -> Self {
+        let published = Arc::new(Published {
+            root: Atomic::new(map.clone()),
+        });
+        Self { map, published }
+    }
+}
+
+// Reads and changes go to the writer's own copy, which readers don't see
+// until the next `publish`.
+impl<K: Ord, V> Deref for EpochTreapMap<K, V> {
+    type Target = PersistentTreapMap<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K: Ord, V> DerefMut for EpochTreapMap<K, V> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+// Replaced versions may be dropped on whichever thread collects them, at any
+// later point, hence the `Send`, `Sync` and `'static` bounds.
+impl<K: Ord + Send + Sync + 'static, V: Send + Sync + 'static> EpochTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        PersistentTreapMap::new().into()
+    }
+
+    #[inline]
+    pub fn reader(&self) -> EpochReader<K, V> {
+        EpochReader(Arc::clone(&self.published))
+    }
+
+    // Does nothing if the map hasn't changed since it was last published.
+    pub fn publish(&mut self) {
+        let guard = epoch::pin();
+        let old = self.published.root.load(Ordering::Relaxed, &guard);
+        // SAFETY: only the writer swaps `root`, so `old` is still the current
+        // version and never null.
+        if unsafe { old.deref() }.ptr_eq(&self.map) {
+            return;
+        }
+        let old = self
+            .published
+            .root
+            .swap(Owned::new(self.map.clone()), Ordering::AcqRel, &guard);
+        // SAFETY: new readers can no longer reach `old`, and those that still
+        // hold it keep the epoch from advancing past them.
+        unsafe { guard.defer_destroy(old) };
+    }
+}
+
+pub struct EpochReader<K: Ord, V>(Arc<Published<K, V>>);
+
+impl<K: Ord, V> Clone for EpochReader<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<K: Ord, V> EpochReader<K, V> {
+    // The latest published version, pinned for as long as the guard lives. A
+    // pinned thread holds back the reclamation of every version replaced in
+    // the meantime, so long reads should prefer `load`.
+    pub fn read(&self) -> EpochGuard<'_, K, V> {
+        let guard = epoch::pin();
+        let map = self.0.root.load(Ordering::Acquire, &guard).as_raw();
+        EpochGuard {
+            map,
+            _guard: guard,
+            _marker: PhantomData,
+        }
+    }
+
+    // An owned clone of the latest published version, which keeps its nodes
+    // alive by reference count instead of by pinning.
+    #[inline]
+    pub fn load(&self) -> PersistentTreapMap<K, V> {
+        self.read().clone()
+    }
+}
+
+pub struct EpochGuard<'a, K: Ord, V> {
+    map: *const PersistentTreapMap<K, V>,
+    _guard: Guard,
+    _marker: PhantomData<&'a PersistentTreapMap<K, V>>,
+}
+
+impl<'a, K: Ord, V> Deref for EpochGuard<'a, K, V> {
+    type Target = PersistentTreapMap<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `map` was published when the guard pinned the thread, so it
+        // can't be dropped before the guard is.
+        unsafe { &*self.map }
+    }
+}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for EpochGuard<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
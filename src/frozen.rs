@@ -0,0 +1,256 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+    ops::{Bound, Range, RangeBounds},
+    slice,
+};
+
+use crate::{rank_bounds, Size, TreapMap};
+
+// A read-only map laid out as two sorted arrays, answering the same queries as
+// `TreapMap` by binary search and indexing.
+pub struct FrozenTreap<K: Ord, V> {
+    keys: Box<[K]>,
+    values: Box<[V]>,
+}
+
+impl<K: Ord, V> Default for FrozenTreap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        TreapMap::new().freeze()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for FrozenTreap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            values: self.values.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for FrozenTreap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for FrozenTreap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys == other.keys && self.values == other.values
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for FrozenTreap<K, V> {}
+
+impl<K: Ord, V> From<TreapMap<K, V>> for FrozenTreap<K, V> {
+    #[inline]
+    fn from(map: TreapMap<K, V>) -> Self {
+        map.freeze()
+    }
+}
+
+impl<K: Ord, V> From<FrozenTreap<K, V>> for TreapMap<K, V> {
+    #[inline]
+    fn from(frozen: FrozenTreap<K, V>) -> Self {
+        frozen.thaw()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a FrozenTreap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = FrozenIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    pub fn freeze(self) -> FrozenTreap<K, V> {
+        let (keys, values): (Vec<_>, Vec<_>) = self.into_iter().unzip();
+        FrozenTreap {
+            keys: keys.into(),
+            values: values.into(),
+        }
+    }
+}
+
+impl<K: Ord, V> FrozenTreap<K, V> {
+    pub fn thaw(self) -> TreapMap<K, V> {
+        TreapMap::from_unique_sorted_iter(
+            self.keys.into_vec().into_iter().zip(self.values.into_vec()),
+        )
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.keys.len() as Size
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    #[inline]
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    #[inline]
+    pub fn values(&self) -> &[V] {
+        &self.values
+    }
+
+    #[inline]
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.rank(key).map(|i| &self.values[i as usize])
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.rank(key).is_some()
+    }
+
+    #[inline]
+    pub fn rank<Q: Ord + ?Sized>(&self, key: &Q) -> Option<Size>
+    where
+        K: Borrow<Q>,
+    {
+        self.keys
+            .binary_search_by(|it| it.borrow().cmp(key))
+            .ok()
+            .map(|i| i as Size)
+    }
+
+    #[inline]
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.keys.partition_point(|it| it.borrow() < key) as Size
+    }
+
+    #[inline]
+    pub fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.keys.partition_point(|it| it.borrow() <= key) as Size
+    }
+
+    #[inline]
+    pub fn nth_kv(&self, n: Size) -> Option<(&K, &V)> {
+        let n = n as usize;
+        Some((self.keys.get(n)?, &self.values[n]))
+    }
+
+    #[inline]
+    pub fn nth(&self, n: Size) -> Option<&V> {
+        self.values.get(n as usize)
+    }
+
+    #[inline]
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        Some((self.keys.first()?, self.values.first()?))
+    }
+
+    #[inline]
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        Some((self.keys.last()?, self.values.last()?))
+    }
+
+    pub fn slice(&self, range: impl RangeBounds<Size>) -> FrozenIter<'_, K, V> {
+        let Range { start: l, end: r } = rank_bounds(&range, self.len());
+        let (l, r) = (l.min(r) as usize, r as usize);
+        FrozenIter {
+            keys: self.keys[l..r].iter(),
+            values: self.values[l..r].iter(),
+        }
+    }
+
+    pub fn range<Q: Ord + ?Sized, R: RangeBounds<Q>>(&self, range: R) -> FrozenIter<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let l = match range.start_bound() {
+            Bound::Included(key) => self.num_lt(key),
+            Bound::Excluded(key) => self.num_le(key),
+            Bound::Unbounded => 0,
+        };
+        let r = match range.end_bound() {
+            Bound::Included(key) => self.num_le(key),
+            Bound::Excluded(key) => self.num_lt(key),
+            Bound::Unbounded => self.len(),
+        };
+        self.slice(l..r)
+    }
+
+    #[inline]
+    pub fn iter(&self) -> FrozenIter<'_, K, V> {
+        FrozenIter {
+            keys: self.keys.iter(),
+            values: self.values.iter(),
+        }
+    }
+}
+
+pub struct FrozenIter<'a, K, V> {
+    keys: slice::Iter<'a, K>,
+    values: slice::Iter<'a, V>,
+}
+
+impl<'a, K, V> Clone for FrozenIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            values: self.values.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for FrozenIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        Some((self.keys.next()?, self.values.next()?))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+
+    #[inline]
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        Some((self.keys.nth(n)?, self.values.nth(n)?))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for FrozenIter<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        Some((self.keys.next_back()?, self.values.next_back()?))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for FrozenIter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for FrozenIter<'a, K, V> {}
+
+impl<'a, K: Debug, V: Debug> Debug for FrozenIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
@@ -0,0 +1,64 @@
+use std::{cell::Cell, ops::Sub};
+
+// Work done by `TreapMap` and the types built on it, counted per thread since
+// the thread started or since `reset_stats`. Subtracting two snapshots gives
+// the work done in between, e.g. by one batch of operations on one map.
+// Splits and merges count once per call, however deep they recurse; only node
+// allocations and key comparisons in the tree itself are counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Stats {
+    pub splits: u64,
+    pub merges: u64,
+    pub allocations: u64,
+    pub comparisons: u64,
+}
+
+impl Sub for Stats {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            splits: self.splits.saturating_sub(rhs.splits),
+            merges: self.merges.saturating_sub(rhs.merges),
+            allocations: self.allocations.saturating_sub(rhs.allocations),
+            comparisons: self.comparisons.saturating_sub(rhs.comparisons),
+        }
+    }
+}
+
+pub(crate) struct Counters {
+    pub splits: Cell<u64>,
+    pub merges: Cell<u64>,
+    pub allocations: Cell<u64>,
+    pub comparisons: Cell<u64>,
+}
+
+thread_local! {
+    pub(crate) static COUNTERS: Counters = const {
+        Counters {
+            splits: Cell::new(0),
+            merges: Cell::new(0),
+            allocations: Cell::new(0),
+            comparisons: Cell::new(0),
+        }
+    };
+}
+
+pub fn stats() -> Stats {
+    COUNTERS.with(|it| Stats {
+        splits: it.splits.get(),
+        merges: it.merges.get(),
+        allocations: it.allocations.get(),
+        comparisons: it.comparisons.get(),
+    })
+}
+
+pub fn reset_stats() {
+    COUNTERS.with(|it| {
+        it.splits.set(0);
+        it.merges.set(0);
+        it.allocations.set(0);
+        it.comparisons.set(0);
+    });
+}
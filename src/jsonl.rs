@@ -0,0 +1,66 @@
+use std::io::{self, BufRead, Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{serde_impl::Collector, TreapMap, TreapSet};
+
+// One `[key, value]` array (or bare key for sets) per line, in key order.
+// Blank lines are ignored when importing.
+fn import_lines<T: DeserializeOwned, R: BufRead>(mut r: R, mut f: impl FnMut(T)) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if !line.trim().is_empty() {
+            f(serde_json::from_str(&line)?);
+        }
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    pub fn export_jsonl<W: Write>(&self, mut w: W) -> io::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        for entry in self.iter() {
+            serde_json::to_writer(&mut w, &entry)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()
+    }
+
+    pub fn import_jsonl<R: BufRead>(r: R) -> io::Result<Self>
+    where
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+    {
+        let mut collector = Collector::new();
+        import_lines(r, |(key, value)| collector.push(key, value))?;
+        Ok(collector.finish())
+    }
+}
+
+impl<K: Ord> TreapSet<K> {
+    pub fn export_jsonl<W: Write>(&self, mut w: W) -> io::Result<()>
+    where
+        K: Serialize,
+    {
+        for key in self.iter() {
+            serde_json::to_writer(&mut w, key)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()
+    }
+
+    pub fn import_jsonl<R: BufRead>(r: R) -> io::Result<Self>
+    where
+        K: DeserializeOwned,
+    {
+        let mut collector = Collector::new();
+        import_lines(r, |key| collector.push(key, ()))?;
+        Ok(Self(collector.finish()))
+    }
+}
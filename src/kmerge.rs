@@ -0,0 +1,114 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+};
+
+use crate::{Iter, TreapMap};
+
+// The next entry of one of the merged maps. Ordered so that `BinaryHeap`
+// pops the smallest key first, and among equal keys the earliest map.
+struct Head<'a, K: Ord, V> {
+    entry: (&'a K, &'a V),
+    source: usize,
+}
+
+impl<'a, K: Ord, V> Clone for Head<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            entry: self.entry,
+            source: self.source,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Ord for Head<'a, K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .entry
+            .0
+            .cmp(self.entry.0)
+            .then(other.source.cmp(&self.source))
+    }
+}
+
+impl<'a, K: Ord, V> PartialOrd for Head<'a, K, V> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, K: Ord, V> PartialEq for Head<'a, K, V> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, K: Ord, V> Eq for Head<'a, K, V> {}
+
+// Entries of several maps in ascending key order. Keys present in more than
+// one map are yielded once per map, in the order the maps were given.
+pub struct MergeIter<'a, K: Ord, V> {
+    iters: Vec<Iter<'a, K, V>>,
+    heap: BinaryHeap<Head<'a, K, V>>,
+}
+
+impl<'a, K: Ord, V> Clone for MergeIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            iters: self.iters.clone(),
+            heap: self.heap.clone(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for MergeIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Head { entry, source } = self.heap.pop()?;
+        if let Some(next) = self.iters[source].next() {
+            self.heap.push(Head {
+                entry: next,
+                source,
+            });
+        }
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.heap.len() + self.iters.iter().map(|it| it.len()).sum::<usize>();
+        (len, Some(len))
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for MergeIter<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for MergeIter<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for MergeIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub fn merge_iters<'a, K: Ord + 'a, V: 'a>(
+    maps: impl IntoIterator<Item = &'a TreapMap<K, V>>,
+) -> MergeIter<'a, K, V> {
+    let mut iters: Vec<_> = maps.into_iter().map(TreapMap::iter).collect();
+    let heap = iters
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(source, it)| {
+            Some(Head {
+                entry: it.next()?,
+                source,
+            })
+        })
+        .collect();
+    MergeIter { iters, heap }
+}
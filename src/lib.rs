@@ -1,35 +1,416 @@
+#![cfg_attr(
+    any(feature = "usize", feature = "wide_weights"),
+    allow(clippy::unnecessary_cast)
+)]
+
 use std::{
+    alloc::{self, Layout},
+    borrow::Borrow,
     cmp::Ordering,
-    iter::Peekable,
-    ops::{Deref, Range},
+    collections::VecDeque,
+    fmt::{self, Debug},
+    hash::{Hash, Hasher},
+    iter::{FusedIterator, Peekable, Rev, StepBy, Zip},
+    ops::{BitAnd, BitOr, BitXor, Bound, Deref, Range, RangeBounds, Sub},
+};
+
+// Bumps a counter of the `instrument` feature, and does nothing without it.
+macro_rules! count {
+    ($counter:ident) => {
+        #[cfg(feature = "instrument")]
+        $crate::instrument::COUNTERS.with(|it| it.$counter.set(it.$counter.get() + 1));
+    };
+}
+
+mod access;
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl;
+#[cfg(feature = "arena")]
+mod arena;
+mod batch;
+#[cfg(feature = "borsh")]
+mod borsh_impl;
+mod buffered;
+#[cfg(feature = "chunked")]
+mod chunked;
+mod convert;
+mod counter;
+mod cursor;
+#[cfg(feature = "epoch")]
+mod epoch;
+mod frozen;
+#[cfg(feature = "instrument")]
+mod instrument;
+#[cfg(feature = "serde_json")]
+mod jsonl;
+mod kmerge;
+mod multimap;
+mod multiset;
+#[cfg(feature = "persistent")]
+mod persistent;
+mod pool;
+mod priority;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "rayon")]
+mod rayon_impl;
+mod rng;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "persistent")]
+mod shared;
+mod small;
+mod snapshot;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+#[cfg(feature = "futures")]
+mod stream;
+mod view;
+pub use access::{AccessCountedTreapMap, AccessIter};
+#[cfg(feature = "arena")]
+pub use arena::{ArenaIter, ArenaTreapMap};
+pub use batch::BatchOp;
+pub use buffered::{BufferedIter, BufferedTreapMap};
+#[cfg(feature = "chunked")]
+pub use chunked::{ChunkedIter, ChunkedTreapMap};
+pub use counter::{CounterIter, TreapCounter};
+pub use cursor::{Cursor, CursorMut};
+#[cfg(feature = "epoch")]
+pub use epoch::{EpochGuard, EpochReader, EpochTreapMap};
+pub use frozen::{FrozenIter, FrozenTreap};
+#[cfg(feature = "instrument")]
+pub use instrument::{reset_stats, stats, Stats};
+pub use kmerge::{merge_iters, MergeIter};
+pub use multimap::{GetAll, TreapMultiMap};
+pub use multiset::TreapMultiSet;
+#[cfg(feature = "persistent")]
+pub use persistent::{PersistentIter, PersistentTreapMap};
+pub use pool::PooledTreapMap;
+pub use priority::{
+    HashPriority, HashedTreapMap, Priority, PriorityTreapMap, RandomPriority,
+    WeightBalancedPriority, WeightBalancedTreapMap, ZipPriority,
 };
+#[cfg(feature = "rand")]
+pub use priority::{RngPriority, SeededTreapMap};
+#[cfg(feature = "rayon")]
+pub use rayon_impl::{ParIter, ParSetIter};
+pub use rng::seed_weights;
+#[cfg(feature = "serde")]
+pub use serde_impl::{SerializeRange, SerializeSetRange};
+#[cfg(feature = "persistent")]
+pub use shared::Snapshot;
+pub use small::{SmallIter, SmallTreapMap};
+pub use snapshot::SnapshotItem;
+#[cfg(feature = "futures")]
+pub use stream::IterStream;
+pub use view::TreapView;
+
+// Subtree sizes, lengths and ranks. The `usize` feature widens them for maps
+// with more than `u32::MAX` entries, at the cost of larger nodes.
+#[cfg(not(feature = "usize"))]
+pub type Size = u32;
+#[cfg(feature = "usize")]
+pub type Size = usize;
+
+// Node priorities; smaller weights sit nearer the root. The `compact` feature
+// narrows them to save space in nodes of small entries, at the cost of more
+// ties between weights. `wide_weights` widens them instead, for maps of tens
+// of millions of entries, and wins if both are enabled.
+#[cfg(not(any(feature = "compact", feature = "wide_weights")))]
+pub type Weight = u32;
+#[cfg(all(feature = "compact", not(feature = "wide_weights")))]
+pub type Weight = u16;
+#[cfg(feature = "wide_weights")]
+pub type Weight = u64;
+
+// The serialized formats store lengths as `u32`.
+#[allow(clippy::useless_conversion)]
+fn len_u32(len: Size) -> Option<u32> {
+    u32::try_from(len).ok()
+}
+
+pub trait IntegerKey: Ord + Copy {
+    fn checked_add_size(self, n: Size) -> Option<Self>;
+}
+
+macro_rules! impl_integer_key {
+    ($($t:ty),*) => {
+        $(
+            impl IntegerKey for $t {
+                #[inline]
+                fn checked_add_size(self, n: Size) -> Option<Self> {
+                    Self::try_from(self as i128 + n as i128).ok()
+                }
+            }
+        )*
+    };
+}
+impl_integer_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+// Depth past which split and merge stop recursing. Balanced trees never get
+// near it.
+const RECURSION_LIMIT: u32 = 256;
+
+// Indices into `NodeData::children`. Code that treats both sides alike takes
+// the side as a `usize` and finds the mirror side at `1 - dir`.
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+
+// Per-subtree data kept in the values, which `maintain_with` updates along
+// with `size`. The split and merge helpers take it as a type parameter, so
+// `multiset::Counts` shares them; plain maps use `()`.
+trait Augment<K: Ord, V> {
+    fn maintain(node: &mut NodeData<K, V>);
+}
+
+impl<K: Ord, V> Augment<K, V> for () {
+    #[inline(always)]
+    fn maintain(_: &mut NodeData<K, V>) {}
+}
 
 struct NodeData<K: Ord, V> {
-    left: TreapMap<K, V>,
-    right: TreapMap<K, V>,
-    size: u32,
+    children: [TreapMap<K, V>; 2],
+    size: Size,
     key: K,
     value: V,
 
-    weight: u32,
+    weight: Weight,
+}
+
+#[inline(always)]
+fn prefetch<T>(x: Option<&T>) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        // Null for a missing child, which is fine to prefetch.
+        let ptr = x.map_or(std::ptr::null(), |it| it as *const T);
+        // SAFETY: prefetching has no effect on memory, and `sse` is part of
+        // the x86_64 baseline.
+        unsafe { _mm_prefetch::<_MM_HINT_T0>(ptr.cast()) };
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = x;
 }
 
 impl<K: Ord, V> NodeData<K, V> {
+    #[inline]
     pub fn new(key: K, value: V) -> Box<Self> {
+        Self::with_weight(key, value, rng::random_weight())
+    }
+
+    fn with_weight(key: K, value: V, weight: Weight) -> Box<Self> {
+        count!(allocations);
         Box::new(Self {
-            left: TreapMap::new(),
-            right: TreapMap::new(),
+            children: Default::default(),
             size: 1,
             key,
             value,
 
-            weight: rand::random(),
+            weight,
         })
     }
 
+    // Like `with_weight`, but hands the entry back if the allocator fails.
+    fn try_with_weight(key: K, value: V, weight: Weight) -> Result<Box<Self>, (K, V)> {
+        // SAFETY: the layout isn't zero-sized, as `size` and `weight` are
+        // always there.
+        let ptr = unsafe { alloc::alloc(Layout::new::<Self>()) }.cast::<Self>();
+        if ptr.is_null() {
+            return Err((key, value));
+        }
+        count!(allocations);
+        // SAFETY: `ptr` is a fresh allocation with the layout `Box` expects.
+        unsafe {
+            ptr.write(Self {
+                children: Default::default(),
+                size: 1,
+                key,
+                value,
+
+                weight,
+            });
+            Ok(Box::from_raw(ptr))
+        }
+    }
+
     #[inline]
     fn maintain(&mut self) {
-        self.size = self.left.len() + self.right.len() + 1;
+        self.size = self.children[LEFT].len() + self.children[RIGHT].len() + 1;
+    }
+
+    #[inline(always)]
+    fn maintain_with<A: Augment<K, V>>(&mut self) {
+        self.maintain();
+        A::maintain(self);
+    }
+
+    // Splits end by joining a node with `(l, r)`, the split of its `dir`
+    // subtree: the part on the node's own side takes that subtree's place,
+    // and the node becomes the root of its side.
+    #[inline(always)]
+    fn relink<A: Augment<K, V>>(
+        mut self: Box<Self>,
+        dir: usize,
+        (l, r): (TreapMap<K, V>, TreapMap<K, V>),
+    ) -> (TreapMap<K, V>, TreapMap<K, V>) {
+        if dir == LEFT {
+            self.children[LEFT] = r;
+            self.maintain_with::<A>();
+            (l, self.into())
+        } else {
+            self.children[RIGHT] = l;
+            self.maintain_with::<A>();
+            (self.into(), r)
+        }
+    }
+
+    // Splits the `dir` subtree with `split` and relinks the parts. Taking the
+    // subtree on a branch, rather than by indexing with `dir`, lets the next
+    // level start loading before the comparison that picked it is done.
+    #[inline(always)]
+    fn split_child<A: Augment<K, V>>(
+        mut self: Box<Self>,
+        dir: usize,
+        split: impl FnOnce(TreapMap<K, V>) -> (TreapMap<K, V>, TreapMap<K, V>),
+    ) -> (TreapMap<K, V>, TreapMap<K, V>) {
+        let parts = if dir == LEFT {
+            split(std::mem::take(&mut self.children[LEFT]))
+        } else {
+            split(std::mem::take(&mut self.children[RIGHT]))
+        };
+        self.relink::<A>(dir, parts)
+    }
+
+    // Splits the keys of this subtree that lie strictly between `lo` and
+    // `hi`, this node's among them, at `key` without touching the tree.
+    // Returns the roots the keys below and above `key` would have after a
+    // real split, and whether `key` is there. Those roots are the topmost
+    // nodes within the bounds on either side of `key` along its search path.
+    fn split_view<'a>(
+        &'a self,
+        key: &K,
+        lo: Option<&K>,
+        hi: Option<&K>,
+    ) -> (Option<&'a Self>, bool, Option<&'a Self>) {
+        let outside = |node: &Self| {
+            lo.is_some_and(|lo| &node.key <= lo) || hi.is_some_and(|hi| &node.key >= hi)
+        };
+        let (mut l, mut r) = (None, None);
+        let mut x = Some(self);
+        while let Some(node) = x {
+            count!(comparisons);
+            match node.key.cmp(key) {
+                Ordering::Less => {
+                    if l.is_none() && !outside(node) {
+                        l = Some(node);
+                    }
+                    x = node.children[RIGHT].0.as_deref();
+                }
+                Ordering::Greater => {
+                    if r.is_none() && !outside(node) {
+                        r = Some(node);
+                    }
+                    x = node.children[LEFT].0.as_deref();
+                }
+                Ordering::Equal => {
+                    // Sides still unaccounted for start below `key`, where
+                    // only the outer bound can rule nodes out.
+                    if l.is_none() {
+                        let mut y = node.children[LEFT].0.as_deref();
+                        while let Some(it) = y.filter(|it| outside(it)) {
+                            y = it.children[RIGHT].0.as_deref();
+                        }
+                        l = y;
+                    }
+                    if r.is_none() {
+                        let mut y = node.children[RIGHT].0.as_deref();
+                        while let Some(it) = y.filter(|it| outside(it)) {
+                            y = it.children[LEFT].0.as_deref();
+                        }
+                        r = y;
+                    }
+                    return (l, true, r);
+                }
+            }
+        }
+        (l, false, r)
+    }
+
+    // The hot descents pick the next subtree with a select on the comparison
+    // rather than a branch, as that is a coin flip for random keys. Without a
+    // branch to speculate past, the next load would wait on the comparison,
+    // so both subtrees are prefetched to overlap it.
+    #[inline(always)]
+    fn child(&self, dir: usize) -> Option<&Self> {
+        let [left, right] = &self.children;
+        let (left, right) = (left.0.as_deref(), right.0.as_deref());
+        prefetch(left);
+        prefetch(right);
+        // Selecting between the already loaded children, rather than indexing
+        // the array by `dir`, saves a load after the comparison.
+        if dir == RIGHT {
+            right
+        } else {
+            left
+        }
+    }
+
+    #[inline(always)]
+    fn child_mut(&mut self, dir: usize) -> Option<&mut Self> {
+        let [left, right] = &mut self.children;
+        let (left, right) = (left.0.as_deref_mut(), right.0.as_deref_mut());
+        prefetch(left.as_deref());
+        prefetch(right.as_deref());
+        if dir == RIGHT {
+            right
+        } else {
+            left
+        }
+    }
+
+    // Pushes the path from `self` down to the `n`-th node of its subtree.
+    fn push_path<'a>(&'a self, mut n: Size, stack: &mut Path<'a, K, V>) {
+        let mut node = self;
+        loop {
+            stack.push(node);
+            let ls = node.children[LEFT].len();
+            match n.cmp(&ls) {
+                Ordering::Less => node = node.children[LEFT].0.as_deref().unwrap(),
+                Ordering::Equal => return,
+                Ordering::Greater => {
+                    n -= ls + 1;
+                    node = node.children[RIGHT].0.as_deref().unwrap();
+                }
+            }
+        }
+    }
+
+    fn print_tree(&self, out: &mut String, prefix: &mut String)
+    where
+        K: Debug,
+    {
+        use std::fmt::Write;
+
+        let _ = writeln!(
+            out,
+            "{:?} (size {}, weight {})",
+            self.key, self.size, self.weight
+        );
+        let children: Vec<_> = [("L", &self.children[LEFT]), ("R", &self.children[RIGHT])]
+            .into_iter()
+            .filter_map(|(side, child)| Some((side, child.0.as_deref()?)))
+            .collect();
+        for (i, (side, child)) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            out.push_str(prefix);
+            out.push_str(if last { "└── " } else { "├── " });
+            out.push_str(side);
+            out.push_str(": ");
+            let len = prefix.len();
+            prefix.push_str(if last { "    " } else { "│   " });
+            child.print_tree(out, prefix);
+            prefix.truncate(len);
+        }
     }
 }
 
@@ -39,6 +420,83 @@ impl<K: Ord, V> Default for TreapMap<K, V> {
         Self::new()
     }
 }
+
+// Dropping the nodes recursively would overflow the stack on a deep tree, so
+// `drop_nodes` frees them iteratively. Empty maps are dropped all the time
+// while splitting and merging, so that check stays inline.
+impl<K: Ord, V> Drop for TreapMap<K, V> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.0.is_some() {
+            self.drop_nodes();
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for TreapMap<K, V> {
+    fn clone(&self) -> Self {
+        let mut stack = vec![(self, false)];
+        let mut built = Vec::new();
+        while let Some((x, visited)) = stack.pop() {
+            let Some(node) = &x.0 else {
+                built.push(Self::new());
+                continue;
+            };
+            if visited {
+                let right = built.pop().unwrap();
+                let left = built.pop().unwrap();
+                count!(allocations);
+                built.push(
+                    Box::new(NodeData {
+                        children: [left, right],
+                        size: node.size,
+                        key: node.key.clone(),
+                        value: node.value.clone(),
+
+                        weight: node.weight,
+                    })
+                    .into(),
+                );
+            } else {
+                stack.push((x, true));
+                stack.push((&node.children[RIGHT], false));
+                stack.push((&node.children[LEFT], false));
+            }
+        }
+        built.pop().unwrap()
+    }
+}
+impl<K: Ord + Debug, V: Debug> Debug for TreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<K: Ord, V: PartialEq> PartialEq for TreapMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+impl<K: Ord, V: Eq> Eq for TreapMap<K, V> {}
+impl<K: Ord + Hash, V: Hash> Hash for TreapMap<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for entry in self.iter() {
+            entry.hash(state);
+        }
+    }
+}
+impl<K: Ord, V: PartialOrd> PartialOrd for TreapMap<K, V> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+impl<K: Ord, V: Ord> Ord for TreapMap<K, V> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
 impl<K: Ord, V> From<Box<NodeData<K, V>>> for TreapMap<K, V> {
     fn from(value: Box<NodeData<K, V>>) -> Self {
         Self(Some(value))
@@ -51,194 +509,652 @@ impl<K: Ord, V> TreapMap<K, V> {
     }
 
     #[inline]
-    pub fn len(&self) -> u32 {
+    fn into_root(mut self) -> Option<Box<NodeData<K, V>>> {
+        self.0.take()
+    }
+
+    // Rotates the left child up until the root has none, then frees the root
+    // and continues with its right subtree; this needs no extra memory.
+    #[inline(never)]
+    fn drop_nodes(&mut self) {
+        let mut cur = self.0.take();
+        while let Some(mut node) = cur {
+            cur = match node.children[LEFT].0.take() {
+                Some(mut left) => {
+                    node.children[LEFT].0 = left.children[RIGHT].0.take();
+                    left.children[RIGHT].0 = Some(node);
+                    Some(left)
+                }
+                None => node.children[RIGHT].0.take(),
+            };
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
         self.0.as_ref().map_or(0, |it| it.size)
     }
 
-    pub fn split_lt(self, key: &K) -> (Self, Self) {
-        let Some(mut x) = self.0 else { return Default::default(); };
-        if key <= &x.key {
-            let (l, r) = x.left.split_lt(key);
-            x.left = r;
-            x.maintain();
-            (l, x.into())
-        } else {
-            let (l, r) = x.right.split_lt(key);
-            x.right = l;
-            x.maintain();
-            (x.into(), r)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    // Panics unless keys ascend strictly, no node outweighs its children and
+    // every size matches its subtree.
+    pub fn validate(&self) {
+        let mut stack: Vec<&NodeData<K, V>> = self.0.as_deref().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            assert!(
+                node.size == node.children[LEFT].len() + node.children[RIGHT].len() + 1,
+                "treap size out of date"
+            );
+            for child in [&node.children[LEFT], &node.children[RIGHT]] {
+                if let Some(child) = child.0.as_deref() {
+                    assert!(child.weight >= node.weight, "treap heap order violated");
+                    stack.push(child);
+                }
+            }
+        }
+        let mut keys = self.keys();
+        if let Some(mut prev) = keys.next() {
+            for key in keys {
+                assert!(prev < key, "treap keys out of order");
+                prev = key;
+            }
         }
     }
 
-    pub fn split_le(self, key: &K) -> (Self, Self) {
-        let Some(mut x) = self.0 else { return Default::default(); };
-        if key < &x.key {
-            let (l, r) = x.left.split_le(key);
-            x.left = r;
-            x.maintain();
-            (l, x.into())
-        } else {
-            let (l, r) = x.right.split_le(key);
-            x.right = l;
-            x.maintain();
-            (x.into(), r)
+    // With the `validate` feature, debug builds re-check the whole tree after
+    // each mutation.
+    #[inline]
+    fn check(&self) {
+        #[cfg(all(feature = "validate", debug_assertions))]
+        self.validate();
+    }
+
+    // Heap bytes taken by the nodes. Memory the keys and values own themselves
+    // (a `String`'s buffer, say) isn't counted.
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.len() as usize * std::mem::size_of::<NodeData<K, V>>()
+    }
+
+    // Detaches the nodes along the search path, each sent left (its right
+    // subtree is then searched) or right as decided by `left`, and relinks
+    // them bottom-up.
+    #[cold]
+    #[inline(never)]
+    fn split_iterative<A: Augment<K, V>, F: FnMut(&NodeData<K, V>) -> bool>(
+        self,
+        left: &mut F,
+    ) -> (Self, Self) {
+        let mut path = Vec::new();
+        let mut cur = self.into_root();
+        while let Some(mut x) = cur {
+            let dir = left(&x) as usize;
+            cur = x.children[dir].0.take();
+            path.push((x, dir));
+        }
+        let mut parts = Default::default();
+        for (x, dir) in path.into_iter().rev() {
+            parts = x.relink::<A>(dir, parts);
+        }
+        parts
+    }
+
+    pub fn split_lt<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        count!(splits);
+        self.split_key_at::<(), false, _>(key, 0)
+    }
+
+    pub fn split_le<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        count!(splits);
+        self.split_key_at::<(), true, _>(key, 0)
+    }
+
+    // Splits off the entries less than `key`, or not greater than it with
+    // `LE`. Plain recursion is the fastest way to split, but a degenerate tree
+    // would overflow the stack, so past `RECURSION_LIMIT` levels the rest of
+    // the path is handled by `split_iterative`. The same goes for `split_n`
+    // and `merge`.
+    fn split_key_at<A: Augment<K, V>, const LE: bool, Q: Ord + ?Sized>(
+        self,
+        key: &Q,
+        depth: u32,
+    ) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let mut left = |x: &NodeData<K, V>| {
+            count!(comparisons);
+            if LE {
+                x.key.borrow() <= key
+            } else {
+                x.key.borrow() < key
+            }
+        };
+        if depth == RECURSION_LIMIT {
+            return self.split_iterative::<A, _>(&mut left);
         }
+        let Some(x) = self.into_root() else { return Default::default(); };
+        let dir = left(&x) as usize;
+        x.split_child::<A>(dir, |it| it.split_key_at::<A, LE, _>(key, depth + 1))
+    }
+
+    #[inline]
+    pub fn split_n(self, n: Size) -> (Self, Self) {
+        count!(splits);
+        self.split_n_at(n, 0)
     }
 
-    pub fn split_n(self, n: u32) -> (Self, Self) {
-        let Some(mut x) = self.0 else { return Default::default(); };
+    fn split_n_at(self, mut n: Size, depth: u32) -> (Self, Self) {
+        if depth == RECURSION_LIMIT {
+            return self.split_iterative::<(), _>(&mut |x| {
+                let ls = x.children[LEFT].len();
+                if n <= ls {
+                    false
+                } else {
+                    n -= ls + 1;
+                    true
+                }
+            });
+        }
+        let Some(x) = self.into_root() else { return Default::default(); };
         if n >= x.size {
             return (x.into(), Self::new());
         }
-        let ls = x.left.len();
-        if n <= ls {
-            let (l, r) = x.left.split_n(n);
-            x.left = r;
-            x.maintain();
-            (l, x.into())
+        let ls = x.children[LEFT].len();
+        let (dir, n) = if n <= ls {
+            (LEFT, n)
         } else {
-            let (l, r) = x.right.split_n(n - ls - 1);
-            x.right = l;
-            x.maintain();
-            (x.into(), r)
-        }
+            (RIGHT, n - ls - 1)
+        };
+        x.split_child::<()>(dir, |it| it.split_n_at(n, depth + 1))
     }
 
+    #[inline]
     pub fn merge(x: Self, y: Self) -> Self {
-        let Some(mut x) = x.0 else { return y };
-        let Some(mut y) = y.0 else { return x.into() };
+        // Equal keys may meet at the seam, as `TreapMultiMap` relies on that.
+        #[cfg(all(feature = "validate", debug_assertions))]
+        if let (Some(a), Some(b)) = (x.last_key_value(), y.first_key_value()) {
+            assert!(a.0 <= b.0, "merging maps with overlapping key ranges");
+        }
+        count!(merges);
+        Self::merge_at::<()>(x, y, 0)
+    }
+
+    fn merge_at<A: Augment<K, V>>(x: Self, y: Self, depth: u32) -> Self {
+        if depth == RECURSION_LIMIT {
+            return Self::merge_iterative::<A>(x, y);
+        }
+        let Some(mut x) = x.into_root() else { return y };
+        let Some(mut y) = y.into_root() else { return x.into() };
+        if x.weight < y.weight {
+            x.children[RIGHT] =
+                Self::merge_at::<A>(std::mem::take(&mut x.children[RIGHT]), y.into(), depth + 1);
+            x.maintain_with::<A>();
+            x.into()
+        } else {
+            y.children[LEFT] =
+                Self::merge_at::<A>(x.into(), std::mem::take(&mut y.children[LEFT]), depth + 1);
+            y.maintain_with::<A>();
+            y.into()
+        }
+    }
+
+    // Walks down the right spine of `x` and the left spine of `y`, then
+    // relinks bottom-up.
+    #[cold]
+    #[inline(never)]
+    fn merge_iterative<A: Augment<K, V>>(x: Self, y: Self) -> Self {
+        let mut path = Vec::new();
+        let (mut x, mut y) = (x.into_root(), y.into_root());
+        let mut res = loop {
+            match (x, y) {
+                (None, rest) | (rest, None) => break Self(rest),
+                (Some(mut a), Some(mut b)) => {
+                    if a.weight < b.weight {
+                        x = a.children[RIGHT].0.take();
+                        y = Some(b);
+                        path.push((a, RIGHT));
+                    } else {
+                        x = Some(a);
+                        y = b.children[LEFT].0.take();
+                        path.push((b, LEFT));
+                    }
+                }
+            }
+        };
+        for (mut node, dir) in path.into_iter().rev() {
+            node.children[dir] = res;
+            node.maintain_with::<A>();
+            res = node.into();
+        }
+        res
+    }
+
+    fn split3<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.split_lt(key);
+        let (m, r) = r.split_le(key);
+        (l, m, r)
+    }
+
+    pub fn split_at_key<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Option<(K, V)>, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, m, r) = self.split3(key);
+        (l, m.into_root().map(|it| (it.key, it.value)), r)
+    }
+
+    fn union(x: Self, y: Self) -> Self {
+        let Some(mut x) = x.into_root() else { return y };
+        let Some(mut y) = y.into_root() else { return x.into() };
         if x.weight < y.weight {
-            x.right = Self::merge(x.right, y.into());
+            let (l, _, r) = Self::from(y).split3(&x.key);
+            let [xl, xr] = std::mem::take(&mut x.children);
+            x.children = [Self::union(xl, l), Self::union(xr, r)];
             x.maintain();
             x.into()
         } else {
-            y.left = Self::merge(x.into(), y.left);
+            let (l, m, r) = Self::from(x).split3(&y.key);
+            let [yl, yr] = std::mem::take(&mut y.children);
+            y.children = [Self::union(l, yl), Self::union(r, yr)];
+            if let Some(m) = m.into_root() {
+                y.key = m.key;
+                y.value = m.value;
+            }
             y.maintain();
             y.into()
         }
     }
 
-    fn get_kv(&self, key: &K) -> Option<(&K, &V)> {
-        let mut x = self;
+    fn intersect(x: Self, y: Self) -> Self {
+        let Some(mut x) = x.into_root() else { return Self::new() };
+        let Some(mut y) = y.into_root() else { return Self::new() };
+        if x.weight < y.weight {
+            let (l, m, r) = Self::from(y).split3(&x.key);
+            let [xl, xr] = std::mem::take(&mut x.children);
+            let l = Self::intersect(xl, l);
+            let r = Self::intersect(xr, r);
+            if m.is_empty() {
+                return Self::merge(l, r);
+            }
+            x.children = [l, r];
+            x.maintain();
+            x.into()
+        } else {
+            let (l, m, r) = Self::from(x).split3(&y.key);
+            let [yl, yr] = std::mem::take(&mut y.children);
+            let l = Self::intersect(l, yl);
+            let r = Self::intersect(r, yr);
+            Self::merge(Self::merge(l, m), r)
+        }
+    }
+
+    fn subtract(x: Self, y: Self) -> Self {
+        let Some(mut x) = x.into_root() else { return Self::new() };
+        let Some(mut y) = y.into_root() else { return x.into() };
+        if x.weight < y.weight {
+            let (l, m, r) = Self::from(y).split3(&x.key);
+            let [xl, xr] = std::mem::take(&mut x.children);
+            let l = Self::subtract(xl, l);
+            let r = Self::subtract(xr, r);
+            if !m.is_empty() {
+                return Self::merge(l, r);
+            }
+            x.children = [l, r];
+            x.maintain();
+            x.into()
+        } else {
+            let (l, _, r) = Self::from(x).split3(&y.key);
+            let [yl, yr] = std::mem::take(&mut y.children);
+            let l = Self::subtract(l, yl);
+            let r = Self::subtract(r, yr);
+            Self::merge(l, r)
+        }
+    }
+
+    fn subtract_ref<W>(x: Self, y: &TreapMap<K, W>) -> Self {
+        let Some(y) = &y.0 else { return x };
+        if x.is_empty() {
+            return x;
+        }
+        let (l, _, r) = x.split3(&y.key);
+        let l = Self::subtract_ref(l, &y.children[LEFT]);
+        let r = Self::subtract_ref(r, &y.children[RIGHT]);
+        Self::merge(l, r)
+    }
+
+    fn xor(x: Self, y: Self) -> Self {
+        let Some(x) = x.into_root() else { return y };
+        let Some(y) = y.into_root() else { return x.into() };
+        let (mut x, y) = if x.weight < y.weight { (x, y) } else { (y, x) };
+        let (l, m, r) = Self::from(y).split3(&x.key);
+        let [xl, xr] = std::mem::take(&mut x.children);
+        let l = Self::xor(xl, l);
+        let r = Self::xor(xr, r);
+        if !m.is_empty() {
+            return Self::merge(l, r);
+        }
+        x.children = [l, r];
+        x.maintain();
+        x.into()
+    }
+
+    // Number of keys in both maps, by the same splits as `intersect`. The
+    // trees are left alone: a part of `other` is its root plus the exclusive
+    // key bounds the splits so far have put on it, and splitting it at a key
+    // of `self` only has to find the roots the two sides would get.
+    fn count_common(&self, other: &Self) -> Size {
+        let mut res = 0;
+        let mut stack = vec![(self, other.0.as_deref(), None, None)];
+        while let Some((x, y, lo, hi)) = stack.pop() {
+            let (Some(x), Some(y)) = (x.0.as_deref(), y) else {
+                continue;
+            };
+            let (l, found, r) = y.split_view(&x.key, lo, hi);
+            res += found as Size;
+            stack.push((&x.children[LEFT], l, lo, Some(&x.key)));
+            stack.push((&x.children[RIGHT], r, Some(&x.key), hi));
+        }
+        res
+    }
+
+    fn get_kv<Q: Ord + ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.0.as_deref();
         loop {
-            let Some(node) = &x.0 else { return None };
-            match key.cmp(&node.key) {
-                Ordering::Less => {
-                    x = &node.left;
-                }
-                Ordering::Equal => {
-                    return Some((&node.key, &node.value));
-                }
-                Ordering::Greater => {
-                    x = &node.right;
-                }
+            let node = x?;
+            count!(comparisons);
+            let ord = key.cmp(node.key.borrow());
+            if ord == Ordering::Equal {
+                return Some((&node.key, &node.value));
             }
+            x = node.child((ord == Ordering::Greater) as usize);
         }
     }
 
-    fn get_kv_mut(&mut self, key: &K) -> Option<(&K, &mut V)> {
-        let mut x = self;
+    fn get_kv_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<(&K, &mut V)>
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.0.as_deref_mut();
         loop {
-            let Some(node) = &mut x.0 else { return None };
-            match key.cmp(&node.key) {
-                Ordering::Less => {
-                    x = &mut node.left;
-                }
-                Ordering::Equal => {
-                    return Some((&node.key, &mut node.value));
-                }
-                Ordering::Greater => {
-                    x = &mut node.right;
-                }
+            let node = x?;
+            count!(comparisons);
+            let ord = key.cmp(node.key.borrow());
+            if ord == Ordering::Equal {
+                return Some((&node.key, &mut node.value));
             }
+            x = node.child_mut((ord == Ordering::Greater) as usize);
         }
     }
 
     #[inline]
-    pub fn get(&self, key: &K) -> Option<&V> {
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
         self.get_kv(key).map(|it| it.1)
     }
 
     #[inline]
-    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
         self.get_kv_mut(key).map(|it| it.1)
     }
 
+    #[inline]
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_weighted(key, value, |_| rng::random_weight())
+    }
+
+    // `weight` is only called when a new node is needed.
+    fn insert_weighted(
+        &mut self,
+        key: K,
+        value: V,
+        weight: impl FnOnce(&K) -> Weight,
+    ) -> Option<V> {
         let (l, mut r) = std::mem::take(self).split_lt(&key);
-        if let Some((k, v)) = r.min_mut() {
+        if let Some((k, v)) = r.first_key_value_mut() {
             if k == &key {
                 let res = std::mem::replace(v, value);
                 *self = Self::merge(l, r);
                 return Some(res);
             }
         }
-        let node = NodeData::new(key, value).into();
+        let weight = weight(&key);
+        let node = NodeData::with_weight(key, value, weight).into();
         *self = Self::merge(Self::merge(l, node), r);
+        self.check();
         None
     }
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        let (l, r) = std::mem::take(self).split_lt(&key);
-        let (m, r) = r.split_le(key);
+    // Like `insert`, but gives the entry back instead of aborting when its
+    // node can't be allocated.
+    pub fn try_insert_alloc(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        if let Some(old) = self.get_mut(&key) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+        let node = NodeData::try_with_weight(key, value, rng::random_weight())?;
+        let (l, r) = std::mem::take(self).split_lt(&node.key);
+        *self = Self::merge(Self::merge(l, node.into()), r);
+        self.check();
+        Ok(None)
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let (l, m, r) = std::mem::take(self).split3(key);
         let mut res = None;
-        if let Some(m) = m.0 {
+        if let Some(m) = m.into_root() {
             res = Some(m.value);
         }
         *self = Self::merge(l, r);
+        self.check();
         res
     }
 
-    pub fn num_lt(&self, key: &K) -> u32 {
-        let mut x = self;
+    pub fn append(&mut self, other: &mut Self) {
+        let x = std::mem::take(self);
+        let y = std::mem::take(other);
+        let before = |x: &Self, y: &Self| match (x.last_key_value(), y.first_key_value()) {
+            (Some(a), Some(b)) => a.0 < b.0,
+            _ => true,
+        };
+        *self = if before(&x, &y) {
+            Self::merge(x, y)
+        } else if before(&y, &x) {
+            Self::merge(y, x)
+        } else {
+            Self::union(y, x)
+        };
+        self.check();
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, V)> {
+        let (l, r) = std::mem::take(self).split_n(1);
+        *self = r;
+        self.check();
+        l.into_root().map(|it| (it.key, it.value))
+    }
+
+    pub fn pop_max(&mut self) -> Option<(K, V)> {
+        let n = self.len().checked_sub(1)?;
+        let (l, r) = std::mem::take(self).split_n(n);
+        *self = l;
+        self.check();
+        r.into_root().map(|it| (it.key, it.value))
+    }
+
+    #[inline]
+    pub fn drain_sorted(&mut self) -> DrainSorted<'_, K, V> {
+        DrainSorted(self)
+    }
+
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.0.as_deref();
         let mut r = 0;
-        while let Some(node) = &x.0 {
-            if key <= &node.key {
-                x = &node.left;
-            } else {
-                r += node.left.len() + 1;
-                x = &node.right;
-            }
+        let mut dir = LEFT;
+        while let Some(node) = x {
+            // Only the sizes of nodes on the path are read, as reading those
+            // of the left children would branch on whether they exist: a
+            // right step counts the node's size minus that of its right child,
+            // subtracted once the descent gets there.
+            r -= node.size * dir as Size;
+            count!(comparisons);
+            dir = (key > node.key.borrow()) as usize;
+            r += node.size * dir as Size;
+            x = node.child(dir);
         }
         r
     }
 
-    pub fn num_le(&self, key: &K) -> u32 {
-        let mut x = self;
+    pub fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        // Counts like `num_lt`.
+        let mut x = self.0.as_deref();
         let mut r = 0;
-        while let Some(node) = &x.0 {
-            if key < &node.key {
-                x = &node.left;
-            } else {
-                r += node.left.len() + 1;
-                x = &node.right;
-            }
+        let mut dir = LEFT;
+        while let Some(node) = x {
+            r -= node.size * dir as Size;
+            count!(comparisons);
+            dir = (key >= node.key.borrow()) as usize;
+            r += node.size * dir as Size;
+            x = node.child(dir);
         }
         r
     }
 
-    pub fn nth_kv(&self, mut n: u32) -> Option<(&K, &V)> {
-        if n >= self.len() {
-            return None;
-        }
-        n += 1;
-        let mut x = self;
+    pub fn rank<Q: Ord + ?Sized>(&self, key: &Q) -> Option<Size>
+    where
+        K: Borrow<Q>,
+    {
+        // Counts like `num_lt`.
+        let mut x = self.0.as_deref();
+        let mut r = 0;
+        let mut dir = LEFT;
         loop {
-            let Some(node) = &x.0 else { unreachable!() };
-            let ls = node.left.len();
-            if n <= ls {
-                x = &node.left;
-            } else {
-                n -= ls + 1;
-                if n == 0 {
-                    break Some((&node.key, &node.value));
-                }
-                x = &node.right;
+            let node = x?;
+            r -= node.size * dir as Size;
+            count!(comparisons);
+            let ord = key.cmp(node.key.borrow());
+            if ord == Ordering::Equal {
+                return Some(r + node.children[LEFT].len());
             }
+            dir = (ord == Ordering::Greater) as usize;
+            r += node.size * dir as Size;
+            x = node.child(dir);
         }
     }
 
-    pub fn nth_kv_mut(&mut self, mut n: u32) -> Option<(&K, &mut V)> {
+    pub fn nearest_kv<D: Ord>(
+        &self,
+        key: &K,
+        mut dist: impl FnMut(&K, &K) -> D,
+    ) -> Option<(&K, &V)> {
+        let mut x = self;
+        let mut floor = None;
+        let mut ceil = None;
+        while let Some(node) = &x.0 {
+            count!(comparisons);
+            match key.cmp(&node.key) {
+                Ordering::Less => {
+                    ceil = Some(node);
+                    x = &node.children[LEFT];
+                }
+                Ordering::Equal => {
+                    return Some((&node.key, &node.value));
+                }
+                Ordering::Greater => {
+                    floor = Some(node);
+                    x = &node.children[RIGHT];
+                }
+            }
+        }
+        let res = match (floor, ceil) {
+            (Some(l), Some(r)) => {
+                if dist(key, &r.key) < dist(key, &l.key) {
+                    r
+                } else {
+                    l
+                }
+            }
+            (l, r) => l.or(r)?,
+        };
+        Some((&res.key, &res.value))
+    }
+
+    // Keys are distinct, so the keys from `start` on are dense up to rank `i`
+    // exactly when the key of rank `i` is `start + (i - r)`. That makes the
+    // subtree sizes the density augmentation, and the first gap is found in
+    // one O(log n) descent after the one for `r`.
+    pub fn first_missing_ge(&self, start: K) -> Option<K>
+    where
+        K: IntegerKey,
+    {
+        let r = self.num_lt(&start);
+        let mut x = self;
+        let mut base = 0;
+        let mut missing = self.len();
+        while let Some(node) = &x.0 {
+            let idx = base + node.children[LEFT].len();
+            if idx < r || start.checked_add_size(idx - r) == Some(node.key) {
+                base = idx + 1;
+                x = &node.children[RIGHT];
+            } else {
+                missing = idx;
+                x = &node.children[LEFT];
+            }
+        }
+        start.checked_add_size(missing - r)
+    }
+
+    pub fn nth_kv(&self, mut n: Size) -> Option<(&K, &V)> {
+        if n >= self.len() {
+            return None;
+        }
+        n += 1;
+        let mut x = self;
+        loop {
+            let Some(node) = &x.0 else { unreachable!() };
+            let ls = node.children[LEFT].len();
+            if n <= ls {
+                x = &node.children[LEFT];
+            } else {
+                n -= ls + 1;
+                if n == 0 {
+                    break Some((&node.key, &node.value));
+                }
+                x = &node.children[RIGHT];
+            }
+        }
+    }
+
+    pub fn nth_kv_mut(&mut self, mut n: Size) -> Option<(&K, &mut V)> {
         if n >= self.len() {
             return None;
         }
@@ -246,353 +1162,2666 @@ impl<K: Ord, V> TreapMap<K, V> {
         let mut x = self;
         loop {
             let Some(node) = &mut x.0 else { unreachable!() };
-            let ls = node.left.len();
+            let ls = node.children[LEFT].len();
             if n <= ls {
-                x = &mut node.left;
+                x = &mut node.children[LEFT];
             } else {
                 n -= ls + 1;
                 if n == 0 {
                     break Some((&node.key, &mut node.value));
                 }
-                x = &mut node.right;
+                x = &mut node.children[RIGHT];
             }
         }
     }
 
+    #[cfg(feature = "rand")]
+    pub fn choose<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        self.nth_kv(rng.gen_range(0..self.len()))
+    }
+
     #[inline]
-    pub fn nth(&self, k: u32) -> Option<&V> {
+    pub fn nth(&self, k: Size) -> Option<&V> {
         self.nth_kv(k).map(|it| it.1)
     }
 
     #[inline]
-    pub fn nth_mut(&mut self, k: u32) -> Option<&mut V> {
+    pub fn nth_mut(&mut self, k: Size) -> Option<&mut V> {
         self.nth_kv_mut(k).map(|it| it.1)
     }
 
-    fn min(&self) -> Option<(&K, &V)> {
-        let Some(mut x) = self.0.as_ref() else { return None };
-        while let Some(y) = &x.left.0 {
-            x = y;
-        }
-        Some((&x.key, &x.value))
-    }
-
-    fn min_mut(&mut self) -> Option<(&K, &mut V)> {
-        let Some(mut x) = self.0.as_mut() else { return None };
-        while let Some(y) = &mut x.left.0 {
+    // The node at the far `dir` end.
+    fn end(&self, dir: usize) -> Option<&NodeData<K, V>> {
+        let mut x = self.0.as_deref()?;
+        while let Some(y) = x.children[dir].0.as_deref() {
             x = y;
         }
-        Some((&x.key, &mut x.value))
+        Some(x)
     }
 
-    fn max(&self) -> Option<(&K, &V)> {
-        let Some(mut x) = self.0.as_ref() else { return None };
-        while let Some(y) = &x.right.0 {
-            x = y;
-        }
-        Some((&x.key, &x.value))
+    #[inline]
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.end(LEFT).map(|it| (&it.key, &it.value))
     }
 
-    fn max_mut(&mut self) -> Option<(&K, &mut V)> {
-        let Some(mut x) = self.0.as_mut() else { return None };
-        while let Some(y) = &mut x.right.0 {
+    fn first_key_value_mut(&mut self) -> Option<(&K, &mut V)> {
+        let mut x = self.0.as_mut()?;
+        while let Some(y) = &mut x.children[LEFT].0 {
             x = y;
         }
         Some((&x.key, &mut x.value))
     }
 
-    pub fn slice(&self, range: Range<u32>) -> Iter<'_, K, V> {
-        let Range { start: l, end: r } = range;
-        let r = r.min(self.len());
-        if l >= r {
-            return Iter {
-                stack: Vec::new(),
-                remaining: 0,
-                rev: false,
-            };
-        }
-        let mut stack: Vec<&NodeData<K, V>> = Vec::new();
-        let mut n = l + 1;
-        let mut x = self;
-        loop {
-            let Some(node) = &x.0 else { unreachable!() };
-            stack.push(node);
-            let ls = node.left.len();
-            if n <= ls {
-                x = &node.left;
-            } else {
-                n -= ls + 1;
-                if n == 0 {
-                    break;
-                }
-                x = &node.right;
-            }
-        }
-        Iter {
-            stack,
-            remaining: r - l,
-            rev: false,
-        }
+    #[inline]
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.end(RIGHT).map(|it| (&it.key, &it.value))
     }
 
-    pub fn rev_slice(&self, range: Range<u32>) -> Iter<'_, K, V> {
-        let Range { start: l, end: r } = range;
-        let r = r.min(self.len());
+    pub fn slice(&self, range: impl RangeBounds<Size>) -> Iter<'_, K, V> {
+        let Range { start: l, end: r } = rank_bounds(&range, self.len());
         if l >= r {
-            return Iter {
-                stack: Vec::new(),
-                remaining: 0,
-                rev: false,
-            };
+            return Iter::empty();
         }
-        let mut iter = self.slice((r - 1)..r);
-        iter.remaining = r - l;
-        iter.rev = true;
+        let root = self.0.as_deref().unwrap();
+        let mut iter = Iter {
+            front: Path::new(),
+            back: Path::new(),
+            remaining: r - l,
+        };
+        root.push_path(l, &mut iter.front);
+        root.push_path(r - 1, &mut iter.back);
         iter
     }
-}
-
-pub struct Iter<'a, K: Ord, V> {
-    stack: Vec<&'a NodeData<K, V>>,
-    remaining: u32,
-    rev: bool,
-}
 
-impl<'a, K: Ord, V> Iter<'a, K, V> {
-    fn move_next(&mut self) {
-        let Some(mut last) = self.stack.pop() else { return };
-        if let Some(mut node) = &last.right.0.as_deref() {
-            self.stack.push(last);
-            loop {
-                self.stack.push(node);
-                node = match &node.left.0 {
-                    Some(x) => x,
-                    None => return,
-                }
-            }
-        }
-        while let Some(parent) = self.stack.pop() {
-            if parent
-                .right
-                .0
-                .as_ref()
-                .map_or(false, |it| std::ptr::eq(it.deref(), last))
-            {
-                last = parent;
-                continue;
+    // Root path to the first entry not less than `key`, along with its rank.
+    fn lower_path<Q: Ord + ?Sized>(&self, key: &Q) -> (Path<'_, K, V>, Size)
+    where
+        K: Borrow<Q>,
+    {
+        let mut path = Path::new();
+        let mut found = 0;
+        let mut rank = 0;
+        let mut x = self;
+        while let Some(node) = x.0.as_deref() {
+            path.push(node);
+            count!(comparisons);
+            if node.key.borrow() < key {
+                rank += node.children[LEFT].len() + 1;
+                x = &node.children[RIGHT];
             } else {
-                self.stack.push(parent);
-                return;
+                found = path.len();
+                x = &node.children[LEFT];
             }
         }
+        path.truncate(found);
+        (path, rank)
     }
 
-    fn move_prev(&mut self) {
-        let Some(mut last) = self.stack.pop() else { return };
-        if let Some(mut node) = &last.left.0.as_deref() {
-            self.stack.push(last);
-            loop {
-                self.stack.push(node);
-                node = match &node.right.0 {
-                    Some(x) => x,
-                    None => return,
-                }
-            }
-        }
-        while let Some(parent) = self.stack.pop() {
-            if parent
-                .left
-                .0
-                .as_ref()
-                .map_or(false, |it| std::ptr::eq(it.deref(), last))
-            {
-                last = parent;
-                continue;
+    // Root path to the last entry not greater than `key`, along with the
+    // number of entries up to and including it.
+    fn upper_path<Q: Ord + ?Sized>(&self, key: &Q) -> (Path<'_, K, V>, Size)
+    where
+        K: Borrow<Q>,
+    {
+        let mut path = Path::new();
+        let mut found = 0;
+        let mut rank = 0;
+        let mut x = self;
+        while let Some(node) = x.0.as_deref() {
+            path.push(node);
+            count!(comparisons);
+            if node.key.borrow() <= key {
+                rank += node.children[LEFT].len() + 1;
+                found = path.len();
+                x = &node.children[RIGHT];
             } else {
-                self.stack.push(parent);
-                return;
+                x = &node.children[LEFT];
             }
         }
+        path.truncate(found);
+        (path, rank)
     }
-}
 
-impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a K, &'a V);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.remaining == 0 {
-            return None; // quick reject
+    pub fn iter_from<Q: Ord + ?Sized>(&self, key: &Q) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let (front, rank) = self.lower_path(key);
+        if rank == self.len() {
+            return Iter::empty();
         }
-        let res = self.stack.last().map(|it| (&it.key, &it.value));
-        self.remaining -= 1;
-        if self.rev {
-            self.move_prev();
-        } else {
-            self.move_next();
+        let mut iter = Iter {
+            front,
+            back: Path::new(),
+            remaining: self.len() - rank,
+        };
+        self.0
+            .as_deref()
+            .unwrap()
+            .push_path(self.len() - 1, &mut iter.back);
+        iter
+    }
+
+    fn iter_to<Q: Ord + ?Sized>(&self, key: &Q) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let (back, rank) = self.upper_path(key);
+        if rank == 0 {
+            return Iter::empty();
         }
-        res
+        let mut iter = Iter {
+            front: Path::new(),
+            back,
+            remaining: rank,
+        };
+        self.0.as_deref().unwrap().push_path(0, &mut iter.front);
+        iter
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.remaining as usize, Some(self.remaining as usize))
+    #[inline]
+    pub fn iter_from_back<Q: Ord + ?Sized>(&self, key: &Q) -> Rev<Iter<'_, K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        self.iter_to(key).rev()
     }
-}
 
-impl<'a, K: Ord, V> ExactSizeIterator for Iter<'a, K, V> {}
+    fn rank_range<Q: Ord + ?Sized, R: RangeBounds<Q>>(&self, range: &R) -> Range<Size>
+    where
+        K: Borrow<Q>,
+    {
+        let l = match range.start_bound() {
+            Bound::Included(key) => self.num_lt(key),
+            Bound::Excluded(key) => self.num_le(key),
+            Bound::Unbounded => 0,
+        };
+        let r = match range.end_bound() {
+            Bound::Included(key) => self.num_le(key),
+            Bound::Excluded(key) => self.num_lt(key),
+            Bound::Unbounded => self.len(),
+        };
+        l..r
+    }
 
-impl<K: Ord, V> TreapMap<K, V> {
-    pub fn from_sorted_iter(iter: impl Iterator<Item = (K, V)>) -> Self {
-        Self::from_unique_sorted_iter(DedupSortedIter(iter.peekable()))
+    pub fn range<Q: Ord + ?Sized, R: RangeBounds<Q>>(&self, range: R) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        self.slice(self.rank_range(&range))
     }
 
-    pub fn from_unique_sorted_iter(iter: impl Iterator<Item = (K, V)>) -> Self {
-        let mut stack: Vec<Box<NodeData<K, V>>> = Vec::new();
-        for (key, value) in iter {
-            let mut node = Box::new(NodeData {
-                left: TreapMap::new(),
-                right: TreapMap::new(),
-                size: 1,
-                key,
-                value,
+    // `ranks` is relative to the entries within `range`.
+    pub fn range_slice<Q: Ord + ?Sized, R: RangeBounds<Q>>(
+        &self,
+        range: R,
+        ranks: impl RangeBounds<Size>,
+    ) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let Range { start: l, end: r } = self.rank_range(&range);
+        let Range { start, end } = rank_bounds(&ranks, r.saturating_sub(l));
+        self.slice(l.saturating_add(start)..l + end)
+    }
 
-                weight: rand::random(),
-            });
-            while let Some(mut top) = stack.pop() {
-                if node.weight < top.weight {
-                    top.right = node.left;
-                    top.maintain();
-                    node.left = top.into();
-                } else {
-                    stack.push(top);
-                    break;
-                }
-            }
-            node.maintain();
-            stack.push(node.into());
-        }
-        while let Some(top) = stack.pop() {
-            let top = top.into();
-            match stack.last_mut() {
-                Some(x) => {
-                    x.right = top;
-                    x.maintain();
-                }
-                None => {
-                    return top;
-                }
-            }
+    pub fn nth_in_range<Q: Ord + ?Sized, R: RangeBounds<Q>>(
+        &self,
+        range: R,
+        n: Size,
+    ) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        let Range { start: l, end: r } = self.rank_range(&range);
+        if n >= r.saturating_sub(l) {
+            return None;
         }
-        Self::new()
+        self.nth_kv(l + n)
     }
-}
 
-impl<K: Ord, V> FromIterator<(K, V)> for TreapMap<K, V> {
-    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
-        let mut vec: Vec<(K, V)> = iter.into_iter().collect();
-        if vec.is_empty() {
-            return Self::new();
-        }
-        vec.sort_by(|x, y| x.0.cmp(&y.0));
-        Self::from_unique_sorted_iter(vec.into_iter())
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.slice(0..self.len())
     }
-}
 
-struct DedupSortedIter<K, V, I: Iterator<Item = (K, V)>>(Peekable<I>);
-impl<K: Eq, V, I: Iterator<Item = (K, V)>> Iterator for DedupSortedIter<K, V, I> {
-    type Item = (K, V);
+    // The entries of rank `range` from the last one down.
+    #[inline]
+    pub fn rev_slice(&self, range: impl RangeBounds<Size>) -> Rev<Iter<'_, K, V>> {
+        self.slice(range).rev()
+    }
 
-    fn next(&mut self) -> Option<(K, V)> {
-        let next = self.0.next()?;
-        loop {
-            let Some(peek) = self.0.peek() else { return Some(next) };
-            if next.0 != peek.0 {
+    // `Iter::nth` seeks by rank, so this costs O(log n) per yielded entry.
+    #[inline]
+    pub fn step_slice(&self, range: impl RangeBounds<Size>, k: Size) -> StepBy<Iter<'_, K, V>> {
+        self.slice(range).step_by(k as usize)
+    }
+
+    pub fn slice_mut(&mut self, range: impl RangeBounds<Size>) -> IterMut<'_, K, V> {
+        let Range {
+            start: mut l,
+            end: mut r,
+        } = rank_bounds(&range, self.len());
+        let mut iter = IterMut {
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+            remaining: r.saturating_sub(l),
+        };
+        if l >= r {
+            return iter;
+        }
+        // Find the topmost node inside the range, then seed each end from
+        // the subtree on its side.
+        let mut x = self;
+        while let TreapMap(Some(node)) = x {
+            let NodeData {
+                children: [left, right],
+                key,
+                value,
+                ..
+            } = &mut **node;
+            let ls = left.len();
+            if r <= ls {
+                x = left;
+            } else if l > ls {
+                l -= ls + 1;
+                r -= ls + 1;
+                x = right;
+            } else {
+                iter.front.push_back((key, value, None));
+                iter.seek_front(left, l);
+                iter.seek_back(right, r - ls - 1);
                 break;
             }
-            self.0.next();
         }
-        Some(next)
+        iter
     }
-}
 
-#[repr(transparent)]
-pub struct TreapSet<K: Ord>(TreapMap<K, ()>);
-impl<K: Ord> Default for TreapSet<K> {
-    fn default() -> Self {
-        Self::new()
+    pub fn range_mut<Q: Ord + ?Sized, R: RangeBounds<Q>>(&mut self, range: R) -> IterMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        let range = self.rank_range(&range);
+        self.slice_mut(range)
     }
-}
-impl<K: Ord> TreapSet<K> {
+
     #[inline]
-    pub fn new() -> Self {
-        Self(TreapMap::new())
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        self.slice_mut(0..self.len())
     }
 
     #[inline]
-    pub fn len(&self) -> u32 {
-        self.0.len()
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.iter())
     }
 
     #[inline]
-    pub fn split(self, key: &K) -> (Self, Self) {
-        let (l, r) = self.0.split_lt(key);
-        (Self(l), Self(r))
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.iter())
     }
 
     #[inline]
-    pub fn split_n(self, n: u32) -> (Self, Self) {
-        let (l, r) = self.0.split_n(n);
-        (Self(l), Self(r))
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut(self.iter_mut())
     }
 
     #[inline]
-    pub fn merge(x: Self, y: Self) -> Self {
-        Self(TreapMap::merge(x.0, y.0))
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys(self.into_iter())
     }
 
     #[inline]
-    pub fn insert(&mut self, key: K) -> bool {
-        self.0.insert(key, ()).is_none()
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues(self.into_iter())
+    }
+
+    pub fn print_tree(&self) -> String
+    where
+        K: Debug,
+    {
+        let mut res = String::new();
+        if let Some(root) = &self.0 {
+            root.print_tree(&mut res, &mut String::new());
+        }
+        res
     }
 
     #[inline]
-    pub fn num_lt(&self, key: &K) -> u32 {
-        self.0.num_lt(key)
+    pub fn enumerate_ranks(&self) -> EnumerateRanks<'_, K, V> {
+        self.iter().enumerate_ranks()
+    }
+
+    // Every adjacent pair of entries in key order.
+    pub fn pairs(&self) -> Pairs<'_, K, V> {
+        let len = self.len();
+        Pairs(self.slice(..len.saturating_sub(1)).zip(self.slice(1..)))
+    }
+
+    // Yields up to `size` consecutive entries at a time; panics if `size` is
+    // zero.
+    pub fn chunks(&self, size: usize) -> Chunks<'_, K, V> {
+        assert!(size != 0, "chunk size must be non-zero");
+        Chunks {
+            iter: self.iter(),
+            size,
+        }
+    }
+
+    // Yields `(depth, key, subtree size)` in breadth-first order.
+    pub fn iter_levels(&self) -> Levels<'_, K, V> {
+        Levels {
+            queue: self.0.as_deref().map(|it| (0, it)).into_iter().collect(),
+        }
     }
+}
+
+// Root paths of up to this many nodes are kept inline by iterators and
+// cursors; random treaps stay below it up to about a million entries.
+const INLINE_PATH: usize = 32;
+
+// A root path that doesn't allocate while it's short. Unused inline slots hold
+// copies of the first node pushed, as there is nothing else to fill them with;
+// an empty path is an empty `Heap`, which doesn't allocate either.
+#[allow(clippy::large_enum_variant)]
+enum Path<'a, K: Ord, V> {
+    Inline(usize, [&'a NodeData<K, V>; INLINE_PATH]),
+    Heap(Vec<&'a NodeData<K, V>>),
+}
 
+impl<'a, K: Ord, V> Clone for Path<'a, K, V> {
     #[inline]
-    pub fn num_le(&self, key: &K) -> u32 {
-        self.0.num_le(key)
+    fn clone(&self) -> Self {
+        match self {
+            Self::Inline(len, nodes) => Self::Inline(*len, *nodes),
+            Self::Heap(nodes) => Self::Heap(nodes.clone()),
+        }
     }
+}
+
+impl<'a, K: Ord, V> Deref for Path<'a, K, V> {
+    type Target = [&'a NodeData<K, V>];
 
     #[inline]
-    pub fn nth(&self, n: u32) -> Option<&K> {
-        self.0.nth_kv(n).map(|it| it.0)
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Inline(len, nodes) => &nodes[..*len],
+            Self::Heap(nodes) => nodes,
+        }
     }
+}
 
+impl<'a, K: Ord, V> Path<'a, K, V> {
     #[inline]
-    pub fn min(&self) -> Option<&K> {
-        self.0.min().map(|it| it.0)
+    fn new() -> Self {
+        Self::Heap(Vec::new())
     }
 
     #[inline]
-    pub fn max(&self) -> Option<&K> {
-        self.0.max().map(|it| it.0)
+    fn push(&mut self, node: &'a NodeData<K, V>) {
+        match self {
+            Self::Inline(len, nodes) => {
+                if let Some(slot) = nodes.get_mut(*len) {
+                    *slot = node;
+                    *len += 1;
+                } else {
+                    self.spill(node);
+                }
+            }
+            Self::Heap(nodes) if nodes.capacity() == 0 => {
+                *self = Self::Inline(1, [node; INLINE_PATH]);
+            }
+            Self::Heap(nodes) => nodes.push(node),
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn spill(&mut self, node: &'a NodeData<K, V>) {
+        let mut nodes = Vec::with_capacity(INLINE_PATH * 2);
+        nodes.extend_from_slice(self);
+        nodes.push(node);
+        *self = Self::Heap(nodes);
     }
 
     #[inline]
-    pub fn get(&self, key: &K) -> Option<&K> {
-        self.0.get_kv(key).map(|it| it.0)
+    fn pop(&mut self) -> Option<&'a NodeData<K, V>> {
+        match self {
+            Self::Inline(len, nodes) => {
+                *len = len.checked_sub(1)?;
+                Some(nodes[*len])
+            }
+            Self::Heap(nodes) => nodes.pop(),
+        }
     }
-}
 
-impl<K: Ord> TreapSet<K> {
     #[inline]
-    pub fn from_sorted_iter(iter: impl Iterator<Item = K>) -> Self {
-        Self(TreapMap::from_sorted_iter(iter.map(|it| (it, ()))))
+    fn truncate(&mut self, n: usize) {
+        match self {
+            Self::Inline(len, _) => *len = n.min(*len),
+            Self::Heap(nodes) => nodes.truncate(n),
+        }
     }
 
     #[inline]
-    pub fn from_unique_sorted_iter(iter: impl Iterator<Item = K>) -> Self {
-        Self(TreapMap::from_unique_sorted_iter(iter.map(|it| (it, ()))))
+    fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+// `front` and `back` are root paths to the next entries to be yielded from
+// either end; `remaining` keeps them from crossing.
+pub struct Iter<'a, K: Ord, V> {
+    front: Path<'a, K, V>,
+    back: Path<'a, K, V>,
+    remaining: Size,
+}
+
+impl<'a, K: Ord, V> Clone for Iter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            front: self.front.clone(),
+            back: self.back.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iter<'a, K, V> {
+    // Pairs every entry with its rank in the whole map.
+    pub fn enumerate_ranks(self) -> EnumerateRanks<'a, K, V> {
+        let rank = if self.remaining == 0 {
+            0
+        } else {
+            Self::rank_of(&self.front)
+        };
+        EnumerateRanks { iter: self, rank }
+    }
+
+    // The first `n` remaining entries (all of them if there are fewer) and
+    // the rest, as independent iterators.
+    pub fn split_at(self, n: usize) -> (Self, Self) {
+        let n = n.min(self.remaining as usize) as Size;
+        if n == 0 {
+            return (Self::empty(), self);
+        }
+        if n == self.remaining {
+            return (self, Self::empty());
+        }
+        let root = self.front[0];
+        let mid = Self::rank_of(&self.front) + n;
+        let mut left = Self {
+            front: self.front,
+            back: Path::new(),
+            remaining: n,
+        };
+        let mut right = Self {
+            front: Path::new(),
+            back: self.back,
+            remaining: self.remaining - n,
+        };
+        root.push_path(mid - 1, &mut left.back);
+        root.push_path(mid, &mut right.front);
+        (left, right)
+    }
+
+    #[inline]
+    pub fn split(self) -> (Self, Self) {
+        let half = self.remaining as usize / 2;
+        self.split_at(half)
+    }
+
+    #[inline]
+    pub fn peek(&self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.front.last().map(|it| (&it.key, &it.value))
+    }
+
+    #[inline]
+    pub fn peek_back(&self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.back.last().map(|it| (&it.key, &it.value))
+    }
+
+    fn empty() -> Self {
+        Self {
+            front: Path::new(),
+            back: Path::new(),
+            remaining: 0,
+        }
+    }
+
+    // In-order walk over `x` that stops once `left` entries have been folded.
+    fn fold_subtree<B, F>(x: &'a TreapMap<K, V>, mut acc: B, left: &mut Size, f: &mut F) -> B
+    where
+        F: FnMut(B, (&'a K, &'a V)) -> B,
+    {
+        let Some(node) = x.0.as_deref() else {
+            return acc;
+        };
+        if *left == 0 {
+            return acc;
+        }
+        acc = Self::fold_subtree(&node.children[LEFT], acc, left, f);
+        if *left == 0 {
+            return acc;
+        }
+        *left -= 1;
+        acc = f(acc, (&node.key, &node.value));
+        Self::fold_subtree(&node.children[RIGHT], acc, left, f)
+    }
+
+    fn rank_of(stack: &[&'a NodeData<K, V>]) -> Size {
+        let mut rank = stack.last().unwrap().children[LEFT].len();
+        for pair in stack.windows(2) {
+            if pair[0].children[RIGHT]
+                .0
+                .as_ref()
+                .is_some_and(|it| std::ptr::eq(it.deref(), pair[1]))
+            {
+                rank += pair[0].children[LEFT].len() + 1;
+            }
+        }
+        rank
+    }
+
+    // Re-seeking from the root costs about as much as climbing out of the
+    // current path, so short skips just step.
+    fn skip_forward(&mut self, n: Size) {
+        if n as usize <= self.front.len() {
+            for _ in 0..n {
+                Self::step(&mut self.front, RIGHT);
+            }
+        } else {
+            let rank = Self::rank_of(&self.front) + n;
+            let root = self.front[0];
+            self.front.clear();
+            root.push_path(rank, &mut self.front);
+        }
+        self.remaining -= n;
+    }
+
+    fn skip_backward(&mut self, n: Size) {
+        if n as usize <= self.back.len() {
+            for _ in 0..n {
+                Self::step(&mut self.back, LEFT);
+            }
+        } else {
+            let rank = Self::rank_of(&self.back) - n;
+            let root = self.back[0];
+            self.back.clear();
+            root.push_path(rank, &mut self.back);
+        }
+        self.remaining -= n;
+    }
+
+    // Steps to the adjacent entry: the next one for `RIGHT`, the previous
+    // one for `LEFT`.
+    fn step(stack: &mut Path<'a, K, V>, dir: usize) {
+        let Some(mut last) = stack.pop() else { return };
+        if let Some(mut node) = last.children[dir].0.as_deref() {
+            stack.push(last);
+            loop {
+                stack.push(node);
+                node = match &node.children[1 - dir].0 {
+                    Some(x) => x,
+                    None => return,
+                }
+            }
+        }
+        while let Some(parent) = stack.pop() {
+            if parent.children[dir]
+                .0
+                .as_ref()
+                .is_some_and(|it| std::ptr::eq(it.deref(), last))
+            {
+                last = parent;
+                continue;
+            } else {
+                stack.push(parent);
+                return;
+            }
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None; // quick reject
+        }
+        let res = self.front.last().map(|it| (&it.key, &it.value));
+        self.remaining -= 1;
+        Self::step(&mut self.front, RIGHT);
+        res
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        if n >= self.remaining as usize {
+            self.remaining = 0;
+            return None;
+        }
+        self.skip_forward(n as Size);
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+
+    // Walks the tree directly instead of stepping the path one entry at a
+    // time: the current node and every ancestor still pending (one we went
+    // left from) is followed by its whole right subtree.
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, Self::Item) -> B,
+    {
+        let mut acc = init;
+        let mut left = self.remaining;
+        let path = &self.front;
+        for (i, &node) in path.iter().enumerate().rev() {
+            if left == 0 {
+                break;
+            }
+            let pending = path.get(i + 1).is_none_or(|&child| {
+                node.children[LEFT]
+                    .0
+                    .as_deref()
+                    .is_some_and(|it| std::ptr::eq(it, child))
+            });
+            if pending {
+                left -= 1;
+                acc = f(acc, (&node.key, &node.value));
+                acc = Self::fold_subtree(&node.children[RIGHT], acc, &mut left, &mut f);
+            }
+        }
+        acc
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    // Entries come out in strictly ascending key order, so the ends are the
+    // extremes.
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let res = self.back.last().map(|it| (&it.key, &it.value));
+        self.remaining -= 1;
+        Self::step(&mut self.back, LEFT);
+        res
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        if n >= self.remaining as usize {
+            self.remaining = 0;
+            return None;
+        }
+        self.skip_backward(n as Size);
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for Iter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+type MutEntry<'a, K, V> = (&'a K, &'a mut V, Option<&'a mut TreapMap<K, V>>);
+
+// Each entry splits a node into disjoint borrows of its key, its value and
+// the one subtree not yet visited, so handing out `&mut V` never aliases the
+// rest of the iterator. `front` is a stack of entries owning their right
+// subtrees with the smallest on top, `back` the mirror image; once one side
+// runs dry it takes over the bottom entry of the other.
+pub struct IterMut<'a, K: Ord, V> {
+    front: VecDeque<MutEntry<'a, K, V>>,
+    back: VecDeque<MutEntry<'a, K, V>>,
+    remaining: Size,
+}
+
+impl<'a, K: Ord, V> IterMut<'a, K, V> {
+    // `front` is the `LEFT` end and `back` the `RIGHT` one.
+    #[inline]
+    fn end_mut(&mut self, dir: usize) -> &mut VecDeque<MutEntry<'a, K, V>> {
+        if dir == LEFT {
+            &mut self.front
+        } else {
+            &mut self.back
+        }
+    }
+
+    // Pushes the `dir` spine of `x` onto the `dir` end.
+    fn push_spine(&mut self, mut x: &'a mut TreapMap<K, V>, dir: usize) {
+        while let TreapMap(Some(node)) = x {
+            let NodeData {
+                children: [left, right],
+                key,
+                value,
+                ..
+            } = &mut **node;
+            let (next, rest) = if dir == LEFT {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            self.end_mut(dir).push_back((key, value, Some(rest)));
+            x = next;
+        }
+    }
+
+    // Takes the entry at the `dir` end.
+    fn next_entry(&mut self, dir: usize) -> Option<(&'a K, &'a mut V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        if self.end_mut(dir).is_empty() {
+            let (key, value, rest) = self.end_mut(1 - dir).pop_front()?;
+            self.end_mut(dir).push_back((key, value, None));
+            if let Some(rest) = rest {
+                self.push_spine(rest, dir);
+            }
+        }
+        let (key, value, rest) = self.end_mut(dir).pop_back()?;
+        self.remaining -= 1;
+        if let Some(rest) = rest {
+            self.push_spine(rest, dir);
+        }
+        Some((key, value))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let front = self.front.iter().rev().flat_map(|(key, value, right)| {
+            std::iter::once((*key, &**value)).chain(right.iter().flat_map(|it| it.iter()))
+        });
+        let back = self.back.iter().flat_map(|(key, value, left)| {
+            left.iter()
+                .flat_map(|it| it.iter())
+                .chain(std::iter::once((*key, &**value)))
+        });
+        front.chain(back)
+    }
+
+    // Pushes the entries of `x` with rank at least `n`.
+    fn seek_front(&mut self, mut x: &'a mut TreapMap<K, V>, mut n: Size) {
+        while let TreapMap(Some(node)) = x {
+            let NodeData {
+                children: [left, right],
+                key,
+                value,
+                ..
+            } = &mut **node;
+            let ls = left.len();
+            if n > ls {
+                n -= ls + 1;
+                x = right;
+                continue;
+            }
+            self.front.push_back((key, value, Some(right)));
+            if n == ls {
+                return;
+            }
+            x = left;
+        }
+    }
+
+    // Pushes the entries of `x` with rank less than `n`.
+    fn seek_back(&mut self, mut x: &'a mut TreapMap<K, V>, mut n: Size) {
+        while let TreapMap(Some(node)) = x {
+            let NodeData {
+                children: [left, right],
+                key,
+                value,
+                ..
+            } = &mut **node;
+            let ls = left.len();
+            if n <= ls {
+                x = left;
+                continue;
+            }
+            self.back.push_back((key, value, Some(left)));
+            n -= ls + 1;
+            if n == 0 {
+                return;
+            }
+            x = right;
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry(LEFT)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_entry(RIGHT)
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for IterMut<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for IterMut<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for IterMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// Same layout as `IterMut`, except that the nodes are owned.
+pub struct IntoIter<K: Ord, V> {
+    front: VecDeque<Box<NodeData<K, V>>>,
+    back: VecDeque<Box<NodeData<K, V>>>,
+    remaining: Size,
+}
+
+impl<K: Ord, V> IntoIter<K, V> {
+    #[inline]
+    fn end_mut(&mut self, dir: usize) -> &mut VecDeque<Box<NodeData<K, V>>> {
+        if dir == LEFT {
+            &mut self.front
+        } else {
+            &mut self.back
+        }
+    }
+
+    fn push_spine(&mut self, mut x: TreapMap<K, V>, dir: usize) {
+        while let Some(mut node) = x.0.take() {
+            x = std::mem::take(&mut node.children[dir]);
+            self.end_mut(dir).push_back(node);
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let front = self.front.iter().rev().flat_map(|node| {
+            std::iter::once((&node.key, &node.value)).chain(node.children[RIGHT].iter())
+        });
+        let back = self.back.iter().flat_map(|node| {
+            node.children[LEFT]
+                .iter()
+                .chain(std::iter::once((&node.key, &node.value)))
+        });
+        front.chain(back)
+    }
+
+    #[inline]
+    fn next_node(&mut self) -> Option<Box<NodeData<K, V>>> {
+        self.take_node(LEFT)
+    }
+
+    // Detaches the node at the `dir` end.
+    fn take_node(&mut self, dir: usize) -> Option<Box<NodeData<K, V>>> {
+        if self.end_mut(dir).is_empty() {
+            let mut node = self.end_mut(1 - dir).pop_front()?;
+            let rest = std::mem::take(&mut node.children[dir]);
+            self.end_mut(dir).push_back(node);
+            self.push_spine(rest, dir);
+        }
+        let mut node = self.end_mut(dir).pop_back()?;
+        self.push_spine(std::mem::take(&mut node.children[1 - dir]), dir);
+        self.remaining -= 1;
+        node.size = 1;
+        Some(node)
+    }
+}
+
+impl<K: Ord, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_node().map(|it| (it.key, it.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.take_node(RIGHT).map(|it| (it.key, it.value))
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: Ord, V> FusedIterator for IntoIter<K, V> {}
+
+impl<K: Ord + Debug, V: Debug> Debug for IntoIter<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a TreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a mut TreapMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K: Ord, V> IntoIterator for TreapMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut iter = IntoIter {
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+            remaining: self.len(),
+        };
+        iter.push_spine(self, LEFT);
+        iter
+    }
+}
+
+pub struct Keys<'a, K: Ord, V>(Iter<'a, K, V>);
+
+impl<'a, K: Ord, V> Clone for Keys<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.0)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, Self::Item) -> B,
+    {
+        self.0.fold(init, |acc, it| f(acc, it.0))
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for Keys<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.0)
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for Keys<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for Keys<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V> Debug for Keys<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub struct Values<'a, K: Ord, V>(Iter<'a, K, V>);
+
+impl<'a, K: Ord, V> Clone for Values<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.1)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, Self::Item) -> B,
+    {
+        self.0.fold(init, |acc, it| f(acc, it.1))
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for Values<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.1)
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for Values<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for Values<'a, K, V> {}
+
+impl<'a, K: Ord, V: Debug> Debug for Values<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub struct ValuesMut<'a, K: Ord, V>(IterMut<'a, K, V>);
+
+impl<'a, K: Ord, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.1)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.1)
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for ValuesMut<'a, K, V> {}
+
+impl<'a, K: Ord, V: Debug> Debug for ValuesMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|it| it.1))
+            .finish()
+    }
+}
+
+pub struct IntoKeys<K: Ord, V>(IntoIter<K, V>);
+
+impl<K: Ord, V> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.0)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for IntoKeys<K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.0)
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for IntoKeys<K, V> {}
+
+impl<K: Ord, V> FusedIterator for IntoKeys<K, V> {}
+
+impl<K: Ord + Debug, V> Debug for IntoKeys<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|it| it.0))
+            .finish()
+    }
+}
+
+pub struct IntoValues<K: Ord, V>(IntoIter<K, V>);
+
+impl<K: Ord, V> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.1)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for IntoValues<K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.1)
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for IntoValues<K, V> {}
+
+impl<K: Ord, V> FusedIterator for IntoValues<K, V> {}
+
+impl<K: Ord, V: Debug> Debug for IntoValues<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|it| it.1))
+            .finish()
+    }
+}
+
+// `rank` is the rank of the next entry from the front.
+pub struct EnumerateRanks<'a, K: Ord, V> {
+    iter: Iter<'a, K, V>,
+    rank: Size,
+}
+
+impl<'a, K: Ord, V> Clone for EnumerateRanks<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            iter: self.iter.clone(),
+            rank: self.rank,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for EnumerateRanks<'a, K, V> {
+    type Item = (Size, &'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.iter.next()?;
+        self.rank += 1;
+        Some((self.rank - 1, key, value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let (key, value) = self.iter.nth(n)?;
+        self.rank += n as Size + 1;
+        Some((self.rank - 1, key, value))
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for EnumerateRanks<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.iter.next_back()?;
+        Some((self.rank + self.iter.remaining, key, value))
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let (key, value) = self.iter.nth_back(n)?;
+        Some((self.rank + self.iter.remaining, key, value))
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for EnumerateRanks<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for EnumerateRanks<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for EnumerateRanks<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+// Every step is a `pop_min` (or `pop_max` from the back), so whatever has
+// not been yielded stays in the map when this is dropped.
+pub struct DrainSorted<'a, K: Ord, V>(&'a mut TreapMap<K, V>);
+
+impl<'a, K: Ord, V> Iterator for DrainSorted<'a, K, V> {
+    type Item = (K, V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.pop_min()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len() as usize, Some(self.0.len() as usize))
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for DrainSorted<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.pop_max()
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for DrainSorted<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for DrainSorted<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for DrainSorted<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter()).finish()
+    }
+}
+
+pub struct Pairs<'a, K: Ord, V>(Zip<Iter<'a, K, V>, Iter<'a, K, V>>);
+
+impl<'a, K: Ord, V> Clone for Pairs<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Pairs<'a, K, V> {
+    type Item = ((&'a K, &'a V), (&'a K, &'a V));
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for Pairs<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for Pairs<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for Pairs<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for Pairs<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub struct Chunks<'a, K: Ord, V> {
+    iter: Iter<'a, K, V>,
+    size: usize,
+}
+
+impl<'a, K: Ord, V> Clone for Chunks<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            iter: self.iter.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Chunks<'a, K, V> {
+    type Item = Vec<(&'a K, &'a V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.iter.len() == 0 {
+            return None;
+        }
+        Some(self.iter.by_ref().take(self.size).collect())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.iter.len().div_ceil(self.size);
+        (len, Some(len))
+    }
+}
+
+// Like `slice::Chunks`, the short chunk (if any) comes first from the back.
+impl<'a, K: Ord, V> DoubleEndedIterator for Chunks<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let len = self.iter.len();
+        if len == 0 {
+            return None;
+        }
+        let size = match len % self.size {
+            0 => self.size,
+            rem => rem,
+        };
+        let mut res: Vec<_> = self.iter.by_ref().rev().take(size).collect();
+        res.reverse();
+        Some(res)
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for Chunks<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for Chunks<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for Chunks<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+// Breadth-first walk over the tree shape, for inspecting balance.
+pub struct Levels<'a, K: Ord, V> {
+    queue: VecDeque<(Size, &'a NodeData<K, V>)>,
+}
+
+impl<'a, K: Ord, V> Clone for Levels<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Levels<'a, K, V> {
+    type Item = (Size, &'a K, Size);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, node) = self.queue.pop_front()?;
+        for child in [&node.children[LEFT], &node.children[RIGHT]] {
+            if let Some(child) = child.0.as_deref() {
+                self.queue.push_back((depth + 1, child));
+            }
+        }
+        Some((depth, &node.key, node.size))
+    }
+}
+
+impl<'a, K: Ord, V> FusedIterator for Levels<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V> Debug for Levels<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    pub fn from_sorted_iter(iter: impl Iterator<Item = (K, V)>) -> Self {
+        Self::from_unique_sorted_iter(DedupSortedIter(iter.peekable()))
+    }
+
+    pub fn from_unique_sorted_iter(iter: impl Iterator<Item = (K, V)>) -> Self {
+        Self::from_sorted_nodes(iter.map(|(key, value)| NodeData::new(key, value)))
+    }
+
+    fn from_sorted_nodes(iter: impl Iterator<Item = Box<NodeData<K, V>>>) -> Self {
+        let mut builder = SortedBuilder(Vec::new());
+        for node in iter {
+            builder.push(node);
+        }
+        builder.finish()
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        let mut iter = std::mem::take(self).into_iter();
+        *self = Self::from_sorted_nodes(
+            std::iter::from_fn(|| iter.next_node())
+                .filter_map(|mut node| f(&node.key, &mut node.value).then_some(node)),
+        );
+        self.check();
+    }
+
+    // Rebuilds the tree at minimal height, then sifts weights down between
+    // nodes (keeping the shape) so the heap order holds for later updates.
+    pub fn rebalance(&mut self) {
+        let n = self.len();
+        let mut iter = std::mem::take(self).into_iter();
+        *self = Self::build_balanced(&mut std::iter::from_fn(|| iter.next_node()), n);
+        self.check();
+    }
+
+    // Rebuilds the tree so that entries of larger `weight` sit nearer the
+    // root, at an expected depth of O(log(total / weight)). Each node draws an
+    // exponential priority with rate `weight + 1`, as in weighted treaps, and
+    // the priorities are then spread evenly over `Weight` by rank, so later
+    // inserts land among them as if every weight had been drawn uniformly.
+    pub fn rebuild_weighted(&mut self, mut weight: impl FnMut(&K, &V) -> u64) {
+        let mut iter = std::mem::take(self).into_iter();
+        let mut nodes: Vec<_> = std::iter::from_fn(|| iter.next_node()).collect();
+        let mut order: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let rate = weight(&node.key, &node.value) as f64 + 1.0;
+                (-rng::random_unit().ln() / rate, i)
+            })
+            .collect();
+        order.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let n = nodes.len() as u128;
+        for (rank, (_, i)) in order.into_iter().enumerate() {
+            nodes[i].weight = (((rank as u128) << Weight::BITS) / n) as Weight;
+        }
+        *self = Self::from_sorted_nodes(nodes.into_iter());
+        self.check();
+    }
+
+    fn build_balanced(iter: &mut impl Iterator<Item = Box<NodeData<K, V>>>, n: Size) -> Self {
+        if n == 0 {
+            return Self::new();
+        }
+        let left = Self::build_balanced(iter, n / 2);
+        let mut node = iter.next().unwrap();
+        node.children = [left, Self::build_balanced(iter, n - n / 2 - 1)];
+        node.maintain();
+        let mut x = &mut *node;
+        loop {
+            let [l, r] = &mut x.children;
+            let child = match (l.0.as_deref_mut(), r.0.as_deref_mut()) {
+                (Some(l), Some(r)) => {
+                    if l.weight <= r.weight {
+                        l
+                    } else {
+                        r
+                    }
+                }
+                (Some(child), None) | (None, Some(child)) => child,
+                (None, None) => break,
+            };
+            if child.weight >= x.weight {
+                break;
+            }
+            std::mem::swap(&mut x.weight, &mut child.weight);
+            x = child;
+        }
+        node.into()
+    }
+}
+
+// Right spine of a treap under construction from nodes given in ascending key order.
+struct SortedBuilder<K: Ord, V>(Vec<Box<NodeData<K, V>>>);
+
+impl<K: Ord, V> SortedBuilder<K, V> {
+    fn push(&mut self, mut node: Box<NodeData<K, V>>) {
+        while let Some(mut top) = self.0.pop() {
+            if node.weight < top.weight {
+                top.children[RIGHT] = std::mem::take(&mut node.children[LEFT]);
+                top.maintain();
+                node.children[LEFT] = top.into();
+            } else {
+                self.0.push(top);
+                break;
+            }
+        }
+        node.maintain();
+        self.0.push(node);
+    }
+
+    fn finish(mut self) -> TreapMap<K, V> {
+        while let Some(top) = self.0.pop() {
+            let top = top.into();
+            match self.0.last_mut() {
+                Some(x) => {
+                    x.children[RIGHT] = top;
+                    x.maintain();
+                }
+                None => {
+                    return top;
+                }
+            }
+        }
+        TreapMap::new()
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for TreapMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut vec: Vec<(K, V)> = iter.into_iter().collect();
+        if vec.is_empty() {
+            return Self::new();
+        }
+        vec.sort_by(|x, y| x.0.cmp(&y.0));
+        Self::from_unique_sorted_iter(vec.into_iter())
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for TreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V: Copy> Extend<(&'a K, &'a V)> for TreapMap<K, V> {
+    #[inline]
+    fn extend<T: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().map(|(&key, &value)| (key, value)));
+    }
+}
+
+struct DedupSortedIter<K, V, I: Iterator<Item = (K, V)>>(Peekable<I>);
+impl<K: Eq, V, I: Iterator<Item = (K, V)>> Iterator for DedupSortedIter<K, V, I> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let next = self.0.next()?;
+        loop {
+            let Some(peek) = self.0.peek() else { return Some(next) };
+            if next.0 != peek.0 {
+                break;
+            }
+            self.0.next();
+        }
+        Some(next)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct TreapSet<K: Ord>(TreapMap<K, ()>);
+impl<K: Ord + Debug> Debug for TreapSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+impl<K: Ord> Default for TreapSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord> TreapSet<K> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(TreapMap::new())
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    #[inline]
+    pub fn rebalance(&mut self) {
+        self.0.rebalance();
+    }
+
+    #[inline]
+    pub fn rebuild_weighted(&mut self, mut weight: impl FnMut(&K) -> u64) {
+        self.0.rebuild_weighted(|key, _| weight(key));
+    }
+
+    #[inline]
+    pub fn validate(&self) {
+        self.0.validate();
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn split<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.0.split_lt(key);
+        (Self(l), Self(r))
+    }
+
+    pub fn split_le<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.0.split_le(key);
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub fn split_at_key<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Option<K>, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, m, r) = self.0.split_at_key(key);
+        (Self(l), m.map(|it| it.0), Self(r))
+    }
+
+    #[inline]
+    pub fn split_n(self, n: Size) -> (Self, Self) {
+        let (l, r) = self.0.split_n(n);
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub fn merge(x: Self, y: Self) -> Self {
+        Self(TreapMap::merge(x.0, y.0))
+    }
+
+    #[inline]
+    pub fn union(self, other: Self) -> Self {
+        let res = Self(TreapMap::union(self.0, other.0));
+        res.0.check();
+        res
+    }
+
+    #[inline]
+    pub fn intersect(self, other: Self) -> Self {
+        let res = Self(TreapMap::intersect(self.0, other.0));
+        res.0.check();
+        res
+    }
+
+    #[inline]
+    pub fn subtract(self, other: Self) -> Self {
+        let res = Self(TreapMap::subtract(self.0, other.0));
+        res.0.check();
+        res
+    }
+
+    // The keys in exactly one of the two sets.
+    #[inline]
+    pub fn symmetric_subtract(self, other: Self) -> Self {
+        let res = Self(TreapMap::xor(self.0, other.0));
+        res.0.check();
+        res
+    }
+
+    #[inline]
+    pub fn append(&mut self, other: &mut Self) {
+        self.0.append(&mut other.0);
+    }
+
+    #[inline]
+    pub fn remove_all(&mut self, other: &Self) {
+        self.0 = TreapMap::subtract_ref(std::mem::take(&mut self.0), &other.0);
+        self.0.check();
+    }
+
+    #[inline]
+    pub fn remove_all_owned(&mut self, other: Self) {
+        *self = std::mem::take(self).subtract(other);
+    }
+
+    #[inline]
+    pub fn insert(&mut self, key: K) -> bool {
+        self.0.insert(key, ()).is_none()
+    }
+
+    #[inline]
+    pub fn try_insert_alloc(&mut self, key: K) -> Result<bool, K> {
+        match self.0.try_insert_alloc(key, ()) {
+            Ok(old) => Ok(old.is_none()),
+            Err((key, ())) => Err(key),
+        }
+    }
+
+    #[inline]
+    pub fn pop_min(&mut self) -> Option<K> {
+        self.0.pop_min().map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn pop_max(&mut self) -> Option<K> {
+        self.0.pop_max().map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn drain_sorted(&mut self) -> SetDrainSorted<'_, K> {
+        SetDrainSorted(self.0.drain_sorted())
+    }
+
+    pub fn get_or_insert(&mut self, key: K) -> &K {
+        let (l, m, r) = std::mem::take(&mut self.0).split3(&key);
+        let m = m.into_root().unwrap_or_else(|| NodeData::new(key, ()));
+        self.rejoin(l, m, r)
+    }
+
+    pub fn get_or_insert_with<Q: Ord + ?Sized>(&mut self, key: &Q, f: impl FnOnce(&Q) -> K) -> &K
+    where
+        K: Borrow<Q>,
+    {
+        let (l, m, r) = std::mem::take(&mut self.0).split3(key);
+        let m = m.into_root().unwrap_or_else(|| NodeData::new(f(key), ()));
+        self.rejoin(l, m, r)
+    }
+
+    // Merges the split pieces back around `m` and returns its key, so that
+    // get-or-insert costs one split and no further descent.
+    fn rejoin(&mut self, l: TreapMap<K, ()>, m: Box<NodeData<K, ()>>, r: TreapMap<K, ()>) -> &K {
+        let key: *const K = &m.key;
+        self.0 = TreapMap::merge(TreapMap::merge(l, m.into()), r);
+        self.0.check();
+        // SAFETY: merging moves the node's `Box`, not the node, and the node
+        // stays in the tree while `self` is borrowed.
+        unsafe { &*key }
+    }
+
+    #[inline]
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.0.num_lt(key)
+    }
+
+    #[inline]
+    pub fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.0.num_le(key)
+    }
+
+    #[inline]
+    pub fn rank<Q: Ord + ?Sized>(&self, key: &Q) -> Option<Size>
+    where
+        K: Borrow<Q>,
+    {
+        self.0.rank(key)
+    }
+
+    #[inline]
+    pub fn nth(&self, n: Size) -> Option<&K> {
+        self.0.nth_kv(n).map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn first_missing_ge(&self, start: K) -> Option<K>
+    where
+        K: IntegerKey,
+    {
+        self.0.first_missing_ge(start)
+    }
+
+    #[inline]
+    pub fn nearest<D: Ord>(&self, key: &K, dist: impl FnMut(&K, &K) -> D) -> Option<&K> {
+        self.0.nearest_kv(key, dist).map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn nth_in_range<Q: Ord + ?Sized, R: RangeBounds<Q>>(&self, range: R, n: Size) -> Option<&K>
+    where
+        K: Borrow<Q>,
+    {
+        self.0.nth_in_range(range, n).map(|it| it.0)
+    }
+
+    #[cfg(feature = "rand")]
+    #[inline]
+    pub fn choose<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<&K> {
+        self.0.choose(rng).map(|it| it.0)
+    }
+
+    // The key of rank `floor(p * (len - 1))`, or `None` if the set is empty or
+    // `p` isn't in `0..=1` (NaN included).
+    #[inline]
+    pub fn quantile(&self, p: f64) -> Option<&K> {
+        self.nth(quantile_rank(self.len(), p)?)
+    }
+
+    #[inline]
+    pub fn median(&self) -> Option<&K> {
+        self.quantile(0.5)
+    }
+
+    #[inline]
+    pub fn first(&self) -> Option<&K> {
+        self.0.first_key_value().map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn last(&self) -> Option<&K> {
+        self.0.last_key_value().map(|it| it.0)
+    }
+
+    // Since sets are `Ord`, method calls resolve `set.min()` and `set.max()`
+    // to `Ord::min` and `Ord::max`, so these are only reachable by path.
+    #[deprecated(note = "`set.min()` resolves to `Ord::min`; use `first` instead")]
+    #[inline]
+    pub fn min(&self) -> Option<&K> {
+        self.first()
+    }
+
+    #[deprecated(note = "`set.max()` resolves to `Ord::max`; use `last` instead")]
+    #[inline]
+    pub fn max(&self) -> Option<&K> {
+        self.last()
+    }
+
+    #[inline]
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+    {
+        self.0.get_kv(key).map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn contains<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.0.get_kv(key).is_some()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> SetIter<'_, K> {
+        SetIter(self.0.iter())
+    }
+
+    #[inline]
+    pub fn slice(&self, range: impl RangeBounds<Size>) -> SetIter<'_, K> {
+        SetIter(self.0.slice(range))
+    }
+
+    #[inline]
+    pub fn rev_slice(&self, range: impl RangeBounds<Size>) -> Rev<SetIter<'_, K>> {
+        self.slice(range).rev()
+    }
+
+    #[inline]
+    pub fn iter_from<Q: Ord + ?Sized>(&self, key: &Q) -> SetIter<'_, K>
+    where
+        K: Borrow<Q>,
+    {
+        SetIter(self.0.iter_from(key))
+    }
+
+    #[inline]
+    pub fn iter_from_back<Q: Ord + ?Sized>(&self, key: &Q) -> Rev<SetIter<'_, K>>
+    where
+        K: Borrow<Q>,
+    {
+        SetIter(self.0.iter_to(key)).rev()
+    }
+
+    #[inline]
+    pub fn step_slice(&self, range: impl RangeBounds<Size>, k: Size) -> StepBy<SetIter<'_, K>> {
+        self.slice(range).step_by(k as usize)
+    }
+
+    #[inline]
+    pub fn range<Q: Ord + ?Sized, R: RangeBounds<Q>>(&self, range: R) -> SetIter<'_, K>
+    where
+        K: Borrow<Q>,
+    {
+        SetIter(self.0.range(range))
+    }
+
+    #[inline]
+    pub fn range_slice<Q: Ord + ?Sized, R: RangeBounds<Q>>(
+        &self,
+        range: R,
+        ranks: impl RangeBounds<Size>,
+    ) -> SetIter<'_, K>
+    where
+        K: Borrow<Q>,
+    {
+        SetIter(self.0.range_slice(range, ranks))
+    }
+
+    #[inline]
+    pub fn union_iter<'a>(&'a self, other: &'a Self) -> Union<'a, K> {
+        Union(self.iter().peekable(), other.iter().peekable())
+    }
+
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, K> {
+        let (small, large) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Intersection(
+            if small.len() as u64 * SEARCH_SIZE_RATIO < large.len() as u64 {
+                IntersectionInner::Search(small.iter(), large)
+            } else {
+                IntersectionInner::Stitch(self.iter(), other.iter())
+            },
+        )
+    }
+
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, K> {
+        Difference(
+            if self.len() as u64 * SEARCH_SIZE_RATIO < other.len() as u64 {
+                DifferenceInner::Search(self.iter(), other)
+            } else {
+                DifferenceInner::Stitch(self.iter(), other.iter().peekable())
+            },
+        )
+    }
+
+    pub fn intersection_len(&self, other: &Self) -> Size {
+        let (small, large) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        small.0.count_common(&large.0)
+    }
+
+    // Panics if the union has more keys than `Size` can count.
+    #[inline]
+    pub fn union_len(&self, other: &Self) -> Size {
+        self.len()
+            .checked_add(other.len() - self.intersection_len(other))
+            .expect("union length overflows `Size`")
+    }
+
+    #[inline]
+    pub fn difference_len(&self, other: &Self) -> Size {
+        self.len() - self.intersection_len(other)
+    }
+
+    #[inline]
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, K> {
+        SymmetricDifference(self.iter().peekable(), other.iter().peekable())
+    }
+
+    #[inline]
+    pub fn retain(&mut self, mut f: impl FnMut(&K) -> bool) {
+        self.0.retain(|key, _| f(key));
+    }
+
+    #[inline]
+    pub fn drain(&mut self) -> SetIntoIter<K> {
+        std::mem::take(self).into_iter()
+    }
+
+    #[inline]
+    pub fn print_tree(&self) -> String
+    where
+        K: Debug,
+    {
+        self.0.print_tree()
+    }
+
+    #[inline]
+    pub fn iter_levels(&self) -> Levels<'_, K, ()> {
+        self.0.iter_levels()
+    }
+}
+
+impl<K: Ord> BitOr for TreapSet<K> {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl<K: Ord> BitAnd for TreapSet<K> {
+    type Output = Self;
+
+    #[inline]
+    fn bitand(self, rhs: Self) -> Self {
+        self.intersect(rhs)
+    }
+}
+
+impl<K: Ord> Sub for TreapSet<K> {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        self.subtract(rhs)
+    }
+}
+
+impl<K: Ord> BitXor for TreapSet<K> {
+    type Output = Self;
+
+    #[inline]
+    fn bitxor(self, rhs: Self) -> Self {
+        self.symmetric_subtract(rhs)
+    }
+}
+
+impl<K: Ord + Clone> BitOr<&TreapSet<K>> for &TreapSet<K> {
+    type Output = TreapSet<K>;
+
+    #[inline]
+    fn bitor(self, rhs: &TreapSet<K>) -> TreapSet<K> {
+        TreapSet::from_unique_sorted_iter(self.union_iter(rhs).cloned())
+    }
+}
+
+impl<K: Ord + Clone> BitAnd<&TreapSet<K>> for &TreapSet<K> {
+    type Output = TreapSet<K>;
+
+    #[inline]
+    fn bitand(self, rhs: &TreapSet<K>) -> TreapSet<K> {
+        TreapSet::from_unique_sorted_iter(self.intersection(rhs).cloned())
+    }
+}
+
+impl<K: Ord + Clone> Sub<&TreapSet<K>> for &TreapSet<K> {
+    type Output = TreapSet<K>;
+
+    #[inline]
+    fn sub(self, rhs: &TreapSet<K>) -> TreapSet<K> {
+        TreapSet::from_unique_sorted_iter(self.difference(rhs).cloned())
+    }
+}
+
+impl<K: Ord + Clone> BitXor<&TreapSet<K>> for &TreapSet<K> {
+    type Output = TreapSet<K>;
+
+    #[inline]
+    fn bitxor(self, rhs: &TreapSet<K>) -> TreapSet<K> {
+        TreapSet::from_unique_sorted_iter(self.symmetric_difference(rhs).cloned())
+    }
+}
+
+// Resolves rank bounds against `len`; the result may be empty or inverted.
+fn rank_bounds(range: &impl RangeBounds<Size>, len: Size) -> Range<Size> {
+    let l = match range.start_bound() {
+        Bound::Included(&l) => l,
+        Bound::Excluded(&l) => l.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let r = match range.end_bound() {
+        Bound::Included(&r) => r.saturating_add(1),
+        Bound::Excluded(&r) => r,
+        Bound::Unbounded => len,
+    };
+    l..r.min(len)
+}
+
+fn quantile_rank(len: Size, p: f64) -> Option<Size> {
+    if !(0.0..=1.0).contains(&p) {
+        return None;
+    }
+    let last = len.checked_sub(1)?;
+    Some((last as f64 * p).floor() as Size)
+}
+
+pub struct SetIter<'a, K: Ord>(Iter<'a, K, ()>);
+
+impl<'a, K: Ord> Clone for SetIter<'a, K> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<'a, K: Ord> SetIter<'a, K> {
+    #[inline]
+    pub fn split_at(self, n: usize) -> (Self, Self) {
+        let (l, r) = self.0.split_at(n);
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub fn split(self) -> (Self, Self) {
+        let (l, r) = self.0.split();
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub fn peek(&self) -> Option<&'a K> {
+        self.0.peek().map(|it| it.0)
+    }
+
+    #[inline]
+    pub fn peek_back(&self) -> Option<&'a K> {
+        self.0.peek_back().map(|it| it.0)
+    }
+}
+
+impl<'a, K: Ord> Iterator for SetIter<'a, K> {
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.0)
+    }
+
+    #[inline]
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.0.nth(n).map(|it| it.0)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, Self::Item) -> B,
+    {
+        self.0.fold(init, |acc, it| f(acc, it.0))
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<'a, K: Ord> DoubleEndedIterator for SetIter<'a, K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.0)
+    }
+
+    #[inline]
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        self.0.nth_back(n).map(|it| it.0)
+    }
+}
+
+impl<'a, K: Ord> ExactSizeIterator for SetIter<'a, K> {}
+
+impl<'a, K: Ord> FusedIterator for SetIter<'a, K> {}
+
+impl<'a, K: Ord + Debug> Debug for SetIter<'a, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub struct SetDrainSorted<'a, K: Ord>(DrainSorted<'a, K, ()>);
+
+impl<'a, K: Ord> Iterator for SetDrainSorted<'a, K> {
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.0)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Ord> DoubleEndedIterator for SetDrainSorted<'a, K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.0)
+    }
+}
+
+impl<'a, K: Ord> ExactSizeIterator for SetDrainSorted<'a, K> {}
+
+impl<'a, K: Ord> FusedIterator for SetDrainSorted<'a, K> {}
+
+impl<'a, K: Ord + Debug> Debug for SetDrainSorted<'a, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries((self.0).0.keys()).finish()
+    }
+}
+
+pub struct SetIntoIter<K: Ord>(IntoIter<K, ()>);
+
+impl<K: Ord> Iterator for SetIntoIter<K> {
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.0)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next()
+    }
+
+    #[inline]
+    fn max(mut self) -> Option<Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        self.next_back()
+    }
+}
+
+impl<K: Ord> DoubleEndedIterator for SetIntoIter<K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|it| it.0)
+    }
+}
+
+impl<K: Ord> ExactSizeIterator for SetIntoIter<K> {}
+
+impl<K: Ord> FusedIterator for SetIntoIter<K> {}
+
+impl<K: Ord + Debug> Debug for SetIntoIter<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|it| it.0))
+            .finish()
+    }
+}
+
+impl<'a, K: Ord> IntoIterator for &'a TreapSet<K> {
+    type Item = &'a K;
+    type IntoIter = SetIter<'a, K>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord> IntoIterator for TreapSet<K> {
+    type Item = K;
+    type IntoIter = SetIntoIter<K>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        SetIntoIter(self.0.into_iter())
+    }
+}
+
+impl<K: Ord> Extend<K> for TreapSet<K> {
+    #[inline]
+    fn extend<T: IntoIterator<Item = K>>(&mut self, iter: T) {
+        self.0.extend(iter.into_iter().map(|key| (key, ())));
+    }
+}
+
+impl<'a, K: Ord + Copy> Extend<&'a K> for TreapSet<K> {
+    #[inline]
+    fn extend<T: IntoIterator<Item = &'a K>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<K: Ord> TreapSet<K> {
+    #[inline]
+    pub fn from_sorted_iter(iter: impl Iterator<Item = K>) -> Self {
+        Self(TreapMap::from_sorted_iter(iter.map(|it| (it, ()))))
+    }
+
+    #[inline]
+    pub fn from_unique_sorted_iter(iter: impl Iterator<Item = K>) -> Self {
+        Self(TreapMap::from_unique_sorted_iter(iter.map(|it| (it, ()))))
+    }
+}
+
+// If one set is this many times larger than the other, looking up each key of
+// the smaller set is cheaper than walking both of them.
+const SEARCH_SIZE_RATIO: u64 = 16;
+
+#[derive(Debug)]
+pub struct Union<'a, K: Ord>(Peekable<SetIter<'a, K>>, Peekable<SetIter<'a, K>>);
+
+impl<'a, K: Ord> Iterator for Union<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (Some(x), Some(y)) = (self.0.peek(), self.1.peek()) else {
+            return self.0.next().or_else(|| self.1.next());
+        };
+        match x.cmp(y) {
+            Ordering::Less => self.0.next(),
+            Ordering::Equal => {
+                self.1.next();
+                self.0.next()
+            }
+            Ordering::Greater => self.1.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (x, y) = (self.0.len(), self.1.len());
+        (x.max(y), Some(x + y))
+    }
+}
+
+#[derive(Debug)]
+pub struct Intersection<'a, K: Ord>(IntersectionInner<'a, K>);
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum IntersectionInner<'a, K: Ord> {
+    Stitch(SetIter<'a, K>, SetIter<'a, K>),
+    Search(SetIter<'a, K>, &'a TreapSet<K>),
+}
+
+impl<'a, K: Ord> Iterator for Intersection<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            IntersectionInner::Stitch(a, b) => {
+                let mut x = a.next()?;
+                let mut y = b.next()?;
+                loop {
+                    match x.cmp(y) {
+                        Ordering::Less => x = a.next()?,
+                        Ordering::Equal => return Some(x),
+                        Ordering::Greater => y = b.next()?,
+                    }
+                }
+            }
+            IntersectionInner::Search(small, large) => small.find(|key| large.get(key).is_some()),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.0 {
+            IntersectionInner::Stitch(a, b) => (0, Some(a.len().min(b.len()))),
+            IntersectionInner::Search(small, _) => (0, Some(small.len())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Difference<'a, K: Ord>(DifferenceInner<'a, K>);
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum DifferenceInner<'a, K: Ord> {
+    Stitch(SetIter<'a, K>, Peekable<SetIter<'a, K>>),
+    Search(SetIter<'a, K>, &'a TreapSet<K>),
+}
+
+impl<'a, K: Ord> Iterator for Difference<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            DifferenceInner::Stitch(a, b) => 'outer: loop {
+                let x = a.next()?;
+                while let Some(y) = b.peek() {
+                    match x.cmp(y) {
+                        Ordering::Less => break,
+                        Ordering::Equal => {
+                            b.next();
+                            continue 'outer;
+                        }
+                        Ordering::Greater => {
+                            b.next();
+                        }
+                    }
+                }
+                break Some(x);
+            },
+            DifferenceInner::Search(a, b) => a.find(|key| b.get(key).is_none()),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.0 {
+            DifferenceInner::Stitch(a, b) => (a.len().saturating_sub(b.len()), Some(a.len())),
+            DifferenceInner::Search(a, _) => (0, Some(a.len())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SymmetricDifference<'a, K: Ord>(Peekable<SetIter<'a, K>>, Peekable<SetIter<'a, K>>);
+
+impl<'a, K: Ord> Iterator for SymmetricDifference<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (Some(x), Some(y)) = (self.0.peek(), self.1.peek()) else {
+                return self.0.next().or_else(|| self.1.next());
+            };
+            match x.cmp(y) {
+                Ordering::Less => return self.0.next(),
+                Ordering::Equal => {
+                    self.0.next();
+                    self.1.next();
+                }
+                Ordering::Greater => return self.1.next(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.0.len() + self.1.len()))
     }
 }
@@ -0,0 +1,133 @@
+use std::fmt::{self, Debug};
+
+use crate::{Iter, NodeData, Size, TreapMap};
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreapMultiMap<K: Ord, V>(TreapMap<K, V>);
+impl<K: Ord + Debug, V: Debug> Debug for TreapMultiMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<K: Ord, V> Default for TreapMultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V> TreapMultiMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(TreapMap::new())
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        let (l, r) = std::mem::take(&mut self.0).split_le(&key);
+        let node = NodeData::new(key, value).into();
+        self.0 = TreapMap::merge(TreapMap::merge(l, node), r);
+    }
+
+    #[inline]
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.get_all(key).next()
+    }
+
+    #[inline]
+    pub fn get_all(&self, key: &K) -> GetAll<'_, K, V> {
+        GetAll(self.0.range(key..=key))
+    }
+
+    #[inline]
+    pub fn count(&self, key: &K) -> Size {
+        self.0.num_le(key) - self.0.num_lt(key)
+    }
+
+    #[inline]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.0.get_kv(key).is_some()
+    }
+
+    pub fn remove_one(&mut self, key: &K) -> Option<V> {
+        let (l, r) = std::mem::take(&mut self.0).split_lt(key);
+        let (m, r) = r.split_n(1);
+        let mut res = None;
+        match m.into_root() {
+            Some(m) if &m.key == key => {
+                res = Some(m.value);
+                self.0 = TreapMap::merge(l, r);
+            }
+            m => {
+                self.0 = TreapMap::merge(l, TreapMap::merge(TreapMap(m), r));
+            }
+        }
+        res
+    }
+
+    pub fn remove_all(&mut self, key: &K) -> Size {
+        let (l, m, r) = std::mem::take(&mut self.0).split3(key);
+        self.0 = TreapMap::merge(l, r);
+        m.len()
+    }
+
+    #[inline]
+    pub fn num_lt(&self, key: &K) -> Size {
+        self.0.num_lt(key)
+    }
+
+    #[inline]
+    pub fn num_le(&self, key: &K) -> Size {
+        self.0.num_le(key)
+    }
+
+    #[inline]
+    pub fn nth(&self, n: Size) -> Option<(&K, &V)> {
+        self.0.nth_kv(n)
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.0.iter()
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for TreapMultiMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut vec: Vec<(K, V)> = iter.into_iter().collect();
+        vec.sort_by(|x, y| x.0.cmp(&y.0));
+        Self(TreapMap::from_unique_sorted_iter(vec.into_iter()))
+    }
+}
+
+#[derive(Debug)]
+pub struct GetAll<'a, K: Ord, V>(Iter<'a, K, V>);
+
+impl<'a, K: Ord, V> Iterator for GetAll<'a, K, V> {
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|it| it.1)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for GetAll<'a, K, V> {}
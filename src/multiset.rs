@@ -0,0 +1,388 @@
+use std::{
+    borrow::Borrow,
+    ops::{Add, Sub},
+};
+
+use crate::{quantile_rank, Augment, Iter, NodeData, Size, TreapMap, LEFT, RIGHT};
+
+// The integer types a `Counts` tree counts in: `Size` for `TreapMultiSet`
+// and `u64` for `TreapCounter`.
+pub(crate) trait Count:
+    Copy + Ord + Default + Add<Output = Self> + Sub<Output = Self>
+{
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+}
+
+macro_rules! impl_count {
+    ($($t:ty),*) => {
+        $(impl Count for $t {
+            #[inline]
+            fn checked_add(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_add(self, rhs)
+            }
+        })*
+    };
+}
+impl_count!(u32, u64, usize);
+
+// A key's count, and the sum of the counts in its subtree.
+#[derive(Clone, Copy)]
+pub(crate) struct Tally<C> {
+    pub(crate) count: C,
+    total: C,
+}
+
+#[inline]
+fn total<K: Ord, C: Count>(x: &TreapMap<K, Tally<C>>) -> C {
+    x.0.as_ref().map_or_else(C::default, |it| it.value.total)
+}
+
+struct Totals;
+
+impl<K: Ord, C: Count> Augment<K, Tally<C>> for Totals {
+    #[inline]
+    fn maintain(node: &mut NodeData<K, Tally<C>>) {
+        node.value.total =
+            total(&node.children[LEFT]) + total(&node.children[RIGHT]) + node.value.count;
+    }
+}
+
+// A treap of distinct keys, each with a count, where every node also sums the
+// counts in its subtree so ranks weighted by them take one descent. It is a
+// `TreapMap` whose splits and merges keep the sums through `Totals`.
+// `TreapMultiSet` and `TreapCounter` are both built on it.
+#[derive(Clone)]
+pub(crate) struct Counts<K: Ord, C>(TreapMap<K, Tally<C>>);
+impl<K: Ord, C> Default for Counts<K, C> {
+    fn default() -> Self {
+        Self(TreapMap::new())
+    }
+}
+
+impl<K: Ord, C: Count> Counts<K, C> {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self(TreapMap::new())
+    }
+
+    // The sum of all counts.
+    #[inline]
+    pub(crate) fn total(&self) -> C {
+        total(&self.0)
+    }
+
+    // The number of distinct keys.
+    #[inline]
+    pub(crate) fn len(&self) -> Size {
+        self.0.len()
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    #[inline]
+    pub(crate) fn iter(&self) -> Iter<'_, K, Tally<C>> {
+        self.0.iter()
+    }
+
+    pub(crate) fn split_lt<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.0.split_key_at::<Totals, false, _>(key, 0);
+        (Self(l), Self(r))
+    }
+
+    pub(crate) fn split_le<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.0.split_key_at::<Totals, true, _>(key, 0);
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub(crate) fn merge(x: Self, y: Self) -> Self {
+        Self(TreapMap::merge_at::<Totals>(x.0, y.0, 0))
+    }
+
+    pub(crate) fn count<Q: Ord + ?Sized>(&self, key: &Q) -> C
+    where
+        K: Borrow<Q>,
+    {
+        self.0.get(key).map_or_else(C::default, |it| it.count)
+    }
+
+    // Returns the key's new count.
+    pub(crate) fn insert_n(&mut self, key: K, n: C) -> C {
+        if n == C::default() {
+            return self.count(&key);
+        }
+        // A key's count never exceeds the total, so checking the total covers
+        // both, and does so before the tree is taken apart.
+        assert!(
+            self.total().checked_add(n).is_some(),
+            "total count overflows its integer type"
+        );
+        let (l, r) = std::mem::take(self).split_lt(&key);
+        let (m, r) = r.split_le(&key);
+        let mut m = m.0.into_root().unwrap_or_else(|| {
+            NodeData::new(
+                key,
+                Tally {
+                    count: C::default(),
+                    total: C::default(),
+                },
+            )
+        });
+        m.value.count = m.value.count + n;
+        m.maintain_with::<Totals>();
+        let res = m.value.count;
+        *self = Self::merge(Self::merge(l, Self(m.into())), r);
+        res
+    }
+
+    // Returns how many were removed, dropping the key once its count is 0.
+    pub(crate) fn remove_n<Q: Ord + ?Sized>(&mut self, key: &Q, n: C) -> C
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = std::mem::take(self).split_lt(key);
+        let (mut m, r) = r.split_le(key);
+        let mut res = C::default();
+        if let Some(mut node) = m.0 .0.take() {
+            res = n.min(node.value.count);
+            node.value.count = node.value.count - res;
+            if node.value.count != C::default() {
+                node.maintain_with::<Totals>();
+                m = Self(node.into());
+            }
+        }
+        *self = Self::merge(Self::merge(l, m), r);
+        res
+    }
+
+    // The sum of the counts of keys less than `key`.
+    pub(crate) fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> C
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.0 .0.as_deref();
+        let mut r = C::default();
+        while let Some(node) = x {
+            if key <= node.key.borrow() {
+                x = node.child(LEFT);
+            } else {
+                r = r + total(&node.children[LEFT]) + node.value.count;
+                x = node.child(RIGHT);
+            }
+        }
+        r
+    }
+
+    pub(crate) fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> C
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = self.0 .0.as_deref();
+        let mut r = C::default();
+        while let Some(node) = x {
+            if key < node.key.borrow() {
+                x = node.child(LEFT);
+            } else {
+                r = r + total(&node.children[LEFT]) + node.value.count;
+                x = node.child(RIGHT);
+            }
+        }
+        r
+    }
+
+    // The key at rank `n` when each key is repeated `count` times.
+    pub(crate) fn nth(&self, mut n: C) -> Option<&K> {
+        let mut x = self.0 .0.as_deref();
+        loop {
+            let node = x?;
+            let ls = total(&node.children[LEFT]);
+            if n < ls {
+                x = node.child(LEFT);
+            } else if n < ls + node.value.count {
+                break Some(&node.key);
+            } else {
+                n = n - (ls + node.value.count);
+                x = node.child(RIGHT);
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn first(&self) -> Option<&K> {
+        self.0.first_key_value().map(|it| it.0)
+    }
+
+    #[inline]
+    pub(crate) fn last(&self) -> Option<&K> {
+        self.0.last_key_value().map(|it| it.0)
+    }
+}
+
+pub struct TreapMultiSet<K: Ord>(Counts<K, Size>);
+impl<K: Ord> Default for TreapMultiSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord> TreapMultiSet<K> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(Counts::new())
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.0.total()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Repeated keys share a node.
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    #[inline]
+    pub fn split_lt<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.0.split_lt(key);
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub fn split_le<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = self.0.split_le(key);
+        (Self(l), Self(r))
+    }
+
+    #[inline]
+    pub fn merge(x: Self, y: Self) -> Self {
+        Self(Counts::merge(x.0, y.0))
+    }
+
+    #[inline]
+    pub fn count<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.0.count(key)
+    }
+
+    #[inline]
+    pub fn contains<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.count(key) != 0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, key: K) {
+        self.insert_n(key, 1);
+    }
+
+    #[inline]
+    pub fn insert_n(&mut self, key: K, n: Size) {
+        self.0.insert_n(key, n);
+    }
+
+    #[inline]
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_n(key, 1) != 0
+    }
+
+    #[inline]
+    pub fn remove_n<Q: Ord + ?Sized>(&mut self, key: &Q, n: Size) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.0.remove_n(key, n)
+    }
+
+    #[inline]
+    pub fn remove_all<Q: Ord + ?Sized>(&mut self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_n(key, Size::MAX)
+    }
+
+    #[inline]
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.0.num_lt(key)
+    }
+
+    #[inline]
+    pub fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.0.num_le(key)
+    }
+
+    #[inline]
+    pub fn nth(&self, n: Size) -> Option<&K> {
+        self.0.nth(n)
+    }
+
+    // Like `TreapSet::quantile`, with ranks counting multiplicities.
+    #[inline]
+    pub fn quantile(&self, p: f64) -> Option<&K> {
+        self.nth(quantile_rank(self.len(), p)?)
+    }
+
+    #[inline]
+    pub fn median(&self) -> Option<&K> {
+        self.quantile(0.5)
+    }
+
+    #[inline]
+    pub fn min(&self) -> Option<&K> {
+        self.0.first()
+    }
+
+    #[inline]
+    pub fn max(&self) -> Option<&K> {
+        self.0.last()
+    }
+}
+
+impl<K: Ord> FromIterator<K> for TreapMultiSet<K> {
+    fn from_iter<T: IntoIterator<Item = K>>(iter: T) -> Self {
+        let mut res = Self::new();
+        for key in iter {
+            res.insert(key);
+        }
+        res
+    }
+}
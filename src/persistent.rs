@@ -0,0 +1,481 @@
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+    mem,
+    sync::Arc,
+};
+
+use crate::{rng, Size, Weight, RECURSION_LIMIT};
+
+#[derive(Clone)]
+struct Node<K, V> {
+    left: Tree<K, V>,
+    right: Tree<K, V>,
+    size: Size,
+    key: K,
+    value: V,
+
+    weight: Weight,
+}
+
+type Tree<K, V> = Option<Arc<Node<K, V>>>;
+
+#[inline]
+fn size<K, V>(tree: &Tree<K, V>) -> Size {
+    tree.as_ref().map_or(0, |it| it.size)
+}
+
+impl<K, V> Node<K, V> {
+    #[inline]
+    fn maintain(&mut self) {
+        self.size = size(&self.left) + size(&self.right) + 1;
+    }
+}
+
+// Every change goes through `Arc::make_mut`, which copies a node still shared
+// with another map and reuses one that isn't, so only the nodes on the paths
+// an operation walks are ever copied. As in `TreapMap`, splits and merges
+// recurse up to `RECURSION_LIMIT` levels and finish the path iteratively.
+fn split_lt<K: Borrow<Q> + Clone, V: Clone, Q: Ord + ?Sized>(
+    tree: Tree<K, V>,
+    key: &Q,
+    depth: u32,
+) -> (Tree<K, V>, Tree<K, V>) {
+    if depth == RECURSION_LIMIT {
+        return split_lt_iterative(tree, key);
+    }
+    let Some(mut x) = tree else {
+        return (None, None);
+    };
+    let node = Arc::make_mut(&mut x);
+    if key <= node.key.borrow() {
+        let (l, r) = split_lt(node.left.take(), key, depth + 1);
+        node.left = r;
+        node.maintain();
+        (l, Some(x))
+    } else {
+        let (l, r) = split_lt(node.right.take(), key, depth + 1);
+        node.right = l;
+        node.maintain();
+        (Some(x), r)
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn split_lt_iterative<K: Borrow<Q> + Clone, V: Clone, Q: Ord + ?Sized>(
+    tree: Tree<K, V>,
+    key: &Q,
+) -> (Tree<K, V>, Tree<K, V>) {
+    let mut path = Vec::new();
+    let mut cur = tree;
+    while let Some(mut x) = cur {
+        let node = Arc::make_mut(&mut x);
+        let side = node.key.borrow() < key;
+        cur = if side {
+            node.right.take()
+        } else {
+            node.left.take()
+        };
+        path.push((x, side));
+    }
+    let (mut l, mut r) = (None, None);
+    // Each node on the path is unshared by now, so `make_mut` copies nothing.
+    for (mut x, side) in path.into_iter().rev() {
+        let node = Arc::make_mut(&mut x);
+        if side {
+            node.right = l;
+            node.maintain();
+            l = Some(x);
+        } else {
+            node.left = r;
+            node.maintain();
+            r = Some(x);
+        }
+    }
+    (l, r)
+}
+
+fn merge<K: Clone, V: Clone>(x: Tree<K, V>, y: Tree<K, V>, depth: u32) -> Tree<K, V> {
+    if depth == RECURSION_LIMIT {
+        return merge_iterative(x, y);
+    }
+    match (x, y) {
+        (None, y) => y,
+        (x, None) => x,
+        (Some(mut x), Some(mut y)) => {
+            if x.weight < y.weight {
+                let node = Arc::make_mut(&mut x);
+                node.right = merge(node.right.take(), Some(y), depth + 1);
+                node.maintain();
+                Some(x)
+            } else {
+                let node = Arc::make_mut(&mut y);
+                node.left = merge(Some(x), node.left.take(), depth + 1);
+                node.maintain();
+                Some(y)
+            }
+        }
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn merge_iterative<K: Clone, V: Clone>(mut x: Tree<K, V>, mut y: Tree<K, V>) -> Tree<K, V> {
+    let mut path = Vec::new();
+    let mut res = loop {
+        match (x, y) {
+            (None, rest) | (rest, None) => break rest,
+            (Some(mut a), Some(mut b)) => {
+                if a.weight < b.weight {
+                    x = Arc::make_mut(&mut a).right.take();
+                    y = Some(b);
+                    path.push((a, true));
+                } else {
+                    x = Some(a);
+                    y = Arc::make_mut(&mut b).left.take();
+                    path.push((b, false));
+                }
+            }
+        }
+    };
+    for (mut x, right) in path.into_iter().rev() {
+        let node = Arc::make_mut(&mut x);
+        if right {
+            node.right = res;
+        } else {
+            node.left = res;
+        }
+        node.maintain();
+        res = Some(x);
+    }
+    res
+}
+
+// A treap whose nodes are reference-counted and shared between clones, so
+// `clone` is O(1) and a map can be handed to a background reader, on another
+// thread if need be, while the original keeps changing. A change to either
+// copy first copies the shared nodes on its path, which costs O(log n) clones
+// of keys and values per operation until the copies have drifted apart.
+pub struct PersistentTreapMap<K: Ord, V>(Tree<K, V>);
+
+impl<K: Ord, V> Default for PersistentTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Takes apart the nodes this map holds the last reference to, without
+// recursing, and stops at those still shared with another map.
+impl<K: Ord, V> Drop for PersistentTreapMap<K, V> {
+    fn drop(&mut self) {
+        let Some(root) = self.0.take() else {
+            return;
+        };
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            // Unlike `try_unwrap`, this can't have two racing drops both fail
+            // and leave the last reference to a recursive drop.
+            if let Some(mut node) = Arc::into_inner(node) {
+                stack.extend(node.left.take());
+                stack.extend(node.right.take());
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> Clone for PersistentTreapMap<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for PersistentTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for PersistentTreapMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for PersistentTreapMap<K, V> {}
+
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for PersistentTreapMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Extend<(K, V)> for PersistentTreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a PersistentTreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = PersistentIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> PersistentTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(None)
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        size(&self.0)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    #[inline]
+    fn into_root(mut self) -> Tree<K, V> {
+        self.0.take()
+    }
+
+    // Whether `self` and `other` are clones that neither has changed since.
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(x), Some(y)) => Arc::ptr_eq(x, y),
+            (x, y) => x.is_none() && y.is_none(),
+        }
+    }
+
+    fn find<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&Node<K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = &self.0;
+        while let Some(node) = x {
+            x = match key.cmp(node.key.borrow()) {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return Some(node),
+                Ordering::Greater => &node.right,
+            };
+        }
+        None
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key).map(|it| &it.value)
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key).is_some()
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut x = self.0.as_ref()?;
+        while let Some(y) = &x.left {
+            x = y;
+        }
+        Some((&x.key, &x.value))
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut x = self.0.as_ref()?;
+        while let Some(y) = &x.right {
+            x = y;
+        }
+        Some((&x.key, &x.value))
+    }
+
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        let mut x = &self.0;
+        let mut res = 0;
+        while let Some(node) = x {
+            if node.key.borrow() < key {
+                res += size(&node.left) + 1;
+                x = &node.right;
+            } else {
+                x = &node.left;
+            }
+        }
+        res
+    }
+
+    pub fn nth(&self, mut n: Size) -> Option<(&K, &V)> {
+        let mut x = &self.0;
+        while let Some(node) = x {
+            let ls = size(&node.left);
+            match n.cmp(&ls) {
+                Ordering::Less => x = &node.left,
+                Ordering::Equal => return Some((&node.key, &node.value)),
+                Ordering::Greater => {
+                    n -= ls + 1;
+                    x = &node.right;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> PersistentIter<'_, K, V> {
+        let mut iter = PersistentIter {
+            stack: Vec::new(),
+            remaining: self.len(),
+        };
+        iter.push_left(&self.0);
+        iter
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> PersistentTreapMap<K, V> {
+    pub fn split_lt<Q: Ord + ?Sized>(self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+    {
+        let (l, r) = split_lt(self.into_root(), key, 0);
+        (Self(l), Self(r))
+    }
+
+    // Every key of `x` must be less than every key of `y`.
+    #[inline]
+    pub fn merge(x: Self, y: Self) -> Self {
+        Self(merge(x.into_root(), y.into_root(), 0))
+    }
+
+    // Looks the key up first, so that a miss copies nothing.
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key)?;
+        let mut x = self.0.as_mut();
+        while let Some(node) = x {
+            let node = Arc::make_mut(node);
+            x = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left.as_mut(),
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Greater => node.right.as_mut(),
+            };
+        }
+        None
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(mem::replace(old, value));
+        }
+        let node = Arc::new(Node {
+            left: None,
+            right: None,
+            size: 1,
+            key,
+            value,
+
+            weight: rng::random_weight(),
+        });
+        let (l, r) = split_lt(self.0.take(), &node.key, 0);
+        self.0 = merge(merge(l, Some(node), 0), r, 0);
+        None
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key)?;
+        let mut x = &mut self.0;
+        loop {
+            let node = Arc::make_mut(x.as_mut().unwrap());
+            node.size -= 1;
+            x = match key.cmp(node.key.borrow()) {
+                Ordering::Less => &mut Arc::make_mut(x.as_mut().unwrap()).left,
+                Ordering::Equal => break,
+                Ordering::Greater => &mut Arc::make_mut(x.as_mut().unwrap()).right,
+            };
+        }
+        // Unshared by the walk above, so this moves the entry out.
+        let node = Arc::try_unwrap(x.take().unwrap()).ok().unwrap();
+        *x = merge(node.left, node.right, 0);
+        Some(node.value)
+    }
+}
+
+pub struct PersistentIter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    remaining: Size,
+}
+
+impl<'a, K, V> Clone for PersistentIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            stack: self.stack.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<'a, K, V> PersistentIter<'a, K, V> {
+    fn push_left(&mut self, mut x: &'a Tree<K, V>) {
+        while let Some(node) = x {
+            self.stack.push(node);
+            x = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for PersistentIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for PersistentIter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for PersistentIter<'a, K, V> {}
+
+impl<'a, K: Debug, V: Debug> Debug for PersistentIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut},
+};
+
+use crate::{rng, Iter, NodeData, TreapMap};
+
+// A `TreapMap` that keeps the allocations of removed nodes and reuses them for
+// later inserts, so insert/remove loops stop going through the global
+// allocator. Everything that doesn't add or remove entries is reached through
+// `Deref`; changes made through `DerefMut` just don't recycle.
+pub struct PooledTreapMap<K: Ord, V> {
+    map: TreapMap<K, V>,
+    free: Vec<Box<MaybeUninit<NodeData<K, V>>>>,
+}
+
+impl<K: Ord, V> Default for PooledTreapMap<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for PooledTreapMap<K, V> {
+    fn clone(&self) -> Self {
+        self.map.clone().into()
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for PooledTreapMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+impl<K: Ord, V> From<TreapMap<K, V>> for PooledTreapMap<K, V> {
+    #[inline]
+    fn from(map: TreapMap<K, V>) -> Self {
+        Self {
+            map,
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<K: Ord, V> Deref for PooledTreapMap<K, V> {
+    type Target = TreapMap<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K: Ord, V> DerefMut for PooledTreapMap<K, V> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a PooledTreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for PooledTreapMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Ord, V> PooledTreapMap<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        TreapMap::new().into()
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> TreapMap<K, V> {
+        self.map
+    }
+
+    // The number of freed nodes waiting to be reused.
+    #[inline]
+    pub fn pool_len(&self) -> usize {
+        self.free.len()
+    }
+
+    // Allocates nodes up front until `n` are waiting in the pool.
+    pub fn reserve_nodes(&mut self, n: usize) {
+        self.free.reserve(n.saturating_sub(self.free.len()));
+        while self.free.len() < n {
+            self.free.push(Box::new_uninit());
+        }
+    }
+
+    // The map's nodes plus those waiting in the pool.
+    pub fn memory_usage(&self) -> usize {
+        self.map.memory_usage()
+            + self.free.len() * mem::size_of::<NodeData<K, V>>()
+            + self.free.capacity() * mem::size_of::<Box<MaybeUninit<NodeData<K, V>>>>()
+    }
+
+    // Releases every pooled node back to the allocator.
+    pub fn shrink_pool(&mut self) {
+        self.free = Vec::new();
+    }
+
+    fn alloc(&mut self, key: K, value: V) -> TreapMap<K, V> {
+        let node = NodeData {
+            children: Default::default(),
+            size: 1,
+            key,
+            value,
+
+            weight: rng::random_weight(),
+        };
+        match self.free.pop() {
+            Some(slot) => Box::write(slot, node),
+            None => Box::new(node),
+        }
+        .into()
+    }
+
+    fn recycle(&mut self, node: Box<NodeData<K, V>>) -> (K, V) {
+        let node = Box::into_raw(node);
+        // SAFETY: `node` came from a live box and is read exactly once, after
+        // which the allocation is only ever handed out again as uninitialized.
+        let NodeData { key, value, .. } = unsafe { node.read() };
+        self.free.push(unsafe { Box::from_raw(node.cast()) });
+        (key, value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.map.get_mut(&key) {
+            return Some(mem::replace(old, value));
+        }
+        let (l, r) = mem::take(&mut self.map).split_lt(&key);
+        let node = self.alloc(key, value);
+        self.map = TreapMap::merge(TreapMap::merge(l, node), r);
+        None
+    }
+
+    #[inline]
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|it| it.1)
+    }
+
+    pub fn remove_entry<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+    {
+        let (l, m, r) = mem::take(&mut self.map).split3(key);
+        self.map = TreapMap::merge(l, r);
+        m.into_root().map(|it| self.recycle(it))
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, V)> {
+        let (l, r) = mem::take(&mut self.map).split_n(1);
+        self.map = r;
+        l.into_root().map(|it| self.recycle(it))
+    }
+
+    pub fn pop_max(&mut self) -> Option<(K, V)> {
+        let n = self.map.len().checked_sub(1)?;
+        let (l, r) = mem::take(&mut self.map).split_n(n);
+        self.map = l;
+        r.into_root().map(|it| self.recycle(it))
+    }
+
+    // Empties the map, keeping all of its nodes in the pool.
+    pub fn clear(&mut self) {
+        self.free.reserve(self.map.len() as usize);
+        let mut stack: Vec<_> = mem::take(&mut self.map).into_root().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.children.iter_mut().filter_map(|it| it.0.take()));
+            self.recycle(node);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    hash::Hash,
+    ops::Deref,
+};
+
+use crate::{rng, Iter, NodeData, Size, TreapMap, Weight, LEFT, RIGHT};
+
+// Where a `PriorityTreapMap` gets the weight of each new node. Smaller weights
+// sit closer to the root, and merging breaks ties in favor of the larger key.
+pub trait Priority<K> {
+    fn weight(&mut self, key: &K) -> Weight;
+
+    // With `Some(alpha)`, every insert or removal also rebuilds the highest
+    // subtree along its path that has more than `alpha` of its entries on one
+    // side of its root, which bounds the depth by log(n) / log(1 / alpha)
+    // whatever the weights are.
+    #[inline]
+    fn balance(&self) -> Option<f64> {
+        None
+    }
+}
+
+// The weights a plain `TreapMap` uses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomPriority;
+
+impl<K> Priority<K> for RandomPriority {
+    #[inline]
+    fn weight(&mut self, _key: &K) -> Weight {
+        rng::random_weight()
+    }
+}
+
+// A fixed, seedless hash of the key. Together with the tie-breaking rule this
+// makes the shape depend only on the set of keys, not on the order of
+// operations, the thread or the machine.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashPriority;
+
+impl<K: Hash> Priority<K> for HashPriority {
+    #[inline]
+    fn weight(&mut self, key: &K) -> Weight {
+        rng::hash_weight(key)
+    }
+}
+
+// Zip-tree ranks: geometrically distributed, so most nodes tie and are ordered
+// by key. The expected depth is a little higher than with uniform weights.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZipPriority;
+
+impl<K> Priority<K> for ZipPriority {
+    #[inline]
+    fn weight(&mut self, _key: &K) -> Weight {
+        Weight::MAX
+            .checked_shr(rng::random_weight().trailing_zeros())
+            .unwrap_or(0)
+    }
+}
+
+// Weight-balanced trees: new nodes come in as leaves, and the shape is kept in
+// check by `balance` alone, so the depth bound holds deterministically. The
+// price is rebuilding subtrees now and then, at amortised O(log n) entries per
+// update. `alpha` must lie in `0.5..1`; smaller values keep the tree
+// shallower but rebuild more often.
+#[derive(Clone, Copy, Debug)]
+pub struct WeightBalancedPriority {
+    alpha: f64,
+}
+
+impl WeightBalancedPriority {
+    #[inline]
+    pub fn new(alpha: f64) -> Self {
+        assert!((0.5..1.0).contains(&alpha), "alpha must lie in 0.5..1");
+        Self { alpha }
+    }
+}
+
+impl Default for WeightBalancedPriority {
+    #[inline]
+    fn default() -> Self {
+        Self::new(0.75)
+    }
+}
+
+impl<K> Priority<K> for WeightBalancedPriority {
+    // Heavier than every rebuilt node, so merging leaves new nodes at the
+    // bottom.
+    #[inline]
+    fn weight(&mut self, _key: &K) -> Weight {
+        Weight::MAX
+    }
+
+    #[inline]
+    fn balance(&self) -> Option<f64> {
+        Some(self.alpha)
+    }
+}
+
+// Uniform weights from a caller-provided RNG, so seeding it makes the shape
+// reproducible.
+#[cfg(feature = "rand")]
+#[derive(Clone, Debug, Default)]
+pub struct RngPriority<R>(pub R);
+
+#[cfg(feature = "rand")]
+impl<K, R: rand::RngCore> Priority<K> for RngPriority<R> {
+    #[inline]
+    fn weight(&mut self, _key: &K) -> Weight {
+        rand::Rng::gen(&mut self.0)
+    }
+}
+
+// A `TreapMap` whose inserts take weights from `P`. Only shared access is
+// exposed through `Deref`, since inserting through the inner map would use
+// random weights.
+pub struct PriorityTreapMap<K: Ord, V, P> {
+    map: TreapMap<K, V>,
+    priority: P,
+}
+
+#[cfg(feature = "rand")]
+pub type SeededTreapMap<K, V, R> = PriorityTreapMap<K, V, RngPriority<R>>;
+pub type HashedTreapMap<K, V> = PriorityTreapMap<K, V, HashPriority>;
+pub type WeightBalancedTreapMap<K, V> = PriorityTreapMap<K, V, WeightBalancedPriority>;
+
+// The weight of a child of a node weighing `weight`. Rebuilt nodes stay
+// below `Weight::MAX` so that new ones sink past them.
+#[inline]
+fn deeper(weight: Weight) -> Weight {
+    if weight < Weight::MAX - 1 {
+        weight + 1
+    } else {
+        weight
+    }
+}
+
+// Builds a tree of minimal height out of `n` nodes in key order, weighing
+// each node by its depth, starting from `weight` at the root.
+fn build_by_depth<K: Ord, V>(
+    iter: &mut impl Iterator<Item = Box<NodeData<K, V>>>,
+    n: Size,
+    weight: Weight,
+) -> TreapMap<K, V> {
+    if n == 0 {
+        return TreapMap::new();
+    }
+    let left = build_by_depth(iter, n / 2, deeper(weight));
+    let mut node = iter.next().unwrap();
+    node.children = [left, build_by_depth(iter, n - n / 2 - 1, deeper(weight))];
+    node.weight = weight;
+    node.maintain();
+    node.into()
+}
+
+// Rebuilds the highest subtree on the path to the gap before rank `gap` that
+// has more than `alpha` of its entries on one side of its root. The subtrees
+// an update changes all lie on the paths to the gaps on either side of it.
+fn restore_balance<K: Ord, V>(map: &mut TreapMap<K, V>, mut gap: Size, alpha: f64) {
+    let mut x = map;
+    let mut weight = 0;
+    loop {
+        let Some(node) = x.0.as_deref() else {
+            return;
+        };
+        let (ls, rs) = (node.children[LEFT].len(), node.children[RIGHT].len());
+        if ls.max(rs) as f64 > alpha * node.size as f64 {
+            break;
+        }
+        weight = deeper(node.weight);
+        let node = x.0.as_deref_mut().unwrap();
+        x = if gap <= ls {
+            &mut node.children[LEFT]
+        } else {
+            gap -= ls + 1;
+            &mut node.children[RIGHT]
+        };
+    }
+    let n = x.len();
+    let mut iter = std::mem::take(x).into_iter();
+    *x = build_by_depth(&mut std::iter::from_fn(|| iter.next_node()), n, weight);
+}
+
+impl<K: Ord, V, P: Default> Default for PriorityTreapMap<K, V, P> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, P: Clone> Clone for PriorityTreapMap<K, V, P> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            priority: self.priority.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Debug, V: Debug, P> Debug for PriorityTreapMap<K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+impl<K: Ord, V: PartialEq, P> PartialEq for PriorityTreapMap<K, V, P> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K: Ord, V: Eq, P> Eq for PriorityTreapMap<K, V, P> {}
+
+impl<K: Ord, V, P> Deref for PriorityTreapMap<K, V, P> {
+    type Target = TreapMap<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<'a, K: Ord, V, P> IntoIterator for &'a PriorityTreapMap<K, V, P> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<K: Ord, V, P: Priority<K> + Default> FromIterator<(K, V)> for PriorityTreapMap<K, V, P> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::default();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord, V, P: Priority<K>> Extend<(K, V)> for PriorityTreapMap<K, V, P> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+impl<K: Ord, V> TreapMap<K, V> {
+    #[inline]
+    pub fn with_rng<R: rand::RngCore>(rng: R) -> SeededTreapMap<K, V, R> {
+        SeededTreapMap::with_rng(rng)
+    }
+}
+
+#[cfg(feature = "rand")]
+impl<K: Ord, V, R: rand::RngCore> SeededTreapMap<K, V, R> {
+    #[inline]
+    pub fn with_rng(rng: R) -> Self {
+        Self::with_priority(RngPriority(rng))
+    }
+
+    #[inline]
+    pub fn rng_mut(&mut self) -> &mut R {
+        &mut self.priority.0
+    }
+}
+
+impl<K: Ord, V, P> PriorityTreapMap<K, V, P> {
+    #[inline]
+    pub fn new() -> Self
+    where
+        P: Default,
+    {
+        Self::with_priority(P::default())
+    }
+
+    #[inline]
+    pub fn with_priority(priority: P) -> Self {
+        Self {
+            map: TreapMap::new(),
+            priority,
+        }
+    }
+
+    #[inline]
+    pub fn priority_mut(&mut self) -> &mut P {
+        &mut self.priority
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> TreapMap<K, V> {
+        self.map
+    }
+
+    #[inline]
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get_mut(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        P: Priority<K>,
+    {
+        let Some(alpha) = self.priority.balance() else {
+            let priority = &mut self.priority;
+            return self
+                .map
+                .insert_weighted(key, value, |key| priority.weight(key));
+        };
+        let rank = self.map.num_lt(&key);
+        let priority = &mut self.priority;
+        let res = self
+            .map
+            .insert_weighted(key, value, |key| priority.weight(key));
+        if res.is_none() {
+            restore_balance(&mut self.map, rank, alpha);
+            restore_balance(&mut self.map, rank + 1, alpha);
+            self.map.check();
+        }
+        res
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        P: Priority<K>,
+    {
+        let res = self.map.remove(key)?;
+        self.removed(self.map.num_lt(key));
+        Some(res)
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, V)>
+    where
+        P: Priority<K>,
+    {
+        let res = self.map.pop_min()?;
+        self.removed(0);
+        Some(res)
+    }
+
+    pub fn pop_max(&mut self) -> Option<(K, V)>
+    where
+        P: Priority<K>,
+    {
+        let res = self.map.pop_max()?;
+        self.removed(self.map.len());
+        Some(res)
+    }
+
+    // Called with the rank the removed entry had.
+    #[inline]
+    fn removed(&mut self, rank: Size)
+    where
+        P: Priority<K>,
+    {
+        if let Some(alpha) = self.priority.balance() {
+            restore_balance(&mut self.map, rank, alpha);
+            self.map.check();
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map = TreapMap::new();
+    }
+}
@@ -0,0 +1,338 @@
+// The `#[pymethods]` expansion trips this lint on every `PyResult` method.
+#![allow(clippy::useless_conversion)]
+
+use std::{cmp::Ordering, mem};
+
+use pyo3::{
+    exceptions::{PyIndexError, PyKeyError},
+    prelude::*,
+    types::{PyDict, PyIterator, PyList},
+};
+
+use crate::{NodeData, Size, TreapMap, TreapSet, LEFT, RIGHT};
+
+// Keys are ordered with Python's own comparison operators, which may raise.
+// The bindings never let the treap compare keys itself: `locate` finds a key
+// with fallible comparisons first, and the treap is then only changed by rank,
+// so a raising `__lt__` comes back as an exception with the map untouched.
+struct PyKey(PyObject);
+
+impl Ord for PyKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        Python::with_gil(|py| self.0.bind(py).compare(other.0.bind(py)))
+            .expect("treap keys must be mutually comparable")
+    }
+}
+
+impl PartialOrd for PyKey {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PyKey {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PyKey {}
+
+// The number of keys below `key`, and whether `key` itself is present.
+fn locate<V>(map: &TreapMap<PyKey, V>, key: &Bound<'_, PyAny>) -> PyResult<(Size, bool)> {
+    let (mut x, mut rank) = (map.0.as_deref(), 0);
+    while let Some(node) = x {
+        let ls = node.children[LEFT].len();
+        match key.compare(node.key.0.bind(key.py()))? {
+            Ordering::Less => x = node.child(LEFT),
+            Ordering::Equal => return Ok((rank + ls, true)),
+            Ordering::Greater => {
+                rank += ls + 1;
+                x = node.child(RIGHT);
+            }
+        }
+    }
+    Ok((rank, false))
+}
+
+fn insert_nth<V>(map: &mut TreapMap<PyKey, V>, n: Size, key: &Bound<'_, PyAny>, value: V) {
+    let (l, r) = mem::take(map).split_n(n);
+    let m = NodeData::new(PyKey(key.clone().unbind()), value).into();
+    *map = TreapMap::merge(TreapMap::merge(l, m), r);
+}
+
+fn remove_nth<V>(map: &mut TreapMap<PyKey, V>, n: Size) -> V {
+    let (l, r) = mem::take(map).split_n(n);
+    let (mut m, r) = r.split_n(1);
+    *map = TreapMap::merge(l, r);
+    m.pop_min().unwrap().1
+}
+
+fn item(py: Python<'_>, (key, value): (&PyKey, &PyObject)) -> (PyObject, PyObject) {
+    (key.0.clone_ref(py), value.clone_ref(py))
+}
+
+#[pyclass(name = "TreapMap")]
+struct PyTreapMap(TreapMap<PyKey, PyObject>);
+
+impl PyTreapMap {
+    fn locate(&self, key: &Bound<'_, PyAny>) -> PyResult<(Size, bool)> {
+        locate(&self.0, key)
+    }
+}
+
+#[pymethods]
+impl PyTreapMap {
+    #[new]
+    #[pyo3(signature = (items = None))]
+    fn new(items: Option<&Bound<'_, PyAny>>) -> PyResult<Self> {
+        let mut res = Self(TreapMap::new());
+        if let Some(items) = items {
+            if let Ok(dict) = items.downcast::<PyDict>() {
+                for (key, value) in dict.iter() {
+                    res.__setitem__(&key, value.unbind())?;
+                }
+            } else {
+                for entry in items.iter()? {
+                    let (key, value): (Bound<'_, PyAny>, PyObject) = entry?.extract()?;
+                    res.__setitem__(&key, value)?;
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len() as usize
+    }
+
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.locate(key)?.1)
+    }
+
+    fn __getitem__(&self, key: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        match self.locate(key)? {
+            (n, true) => Ok(self.0.nth(n).unwrap().clone_ref(key.py())),
+            (_, false) => Err(PyKeyError::new_err(key.clone().unbind())),
+        }
+    }
+
+    fn __setitem__(&mut self, key: &Bound<'_, PyAny>, value: PyObject) -> PyResult<()> {
+        match self.locate(key)? {
+            (n, true) => *self.0.nth_mut(n).unwrap() = value,
+            (n, false) => insert_nth(&mut self.0, n, key, value),
+        }
+        Ok(())
+    }
+
+    fn __delitem__(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.pop(key, None).map(drop)
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        PyList::new_bound(py, self.keys(py)).into_any().iter()
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let mut entries = Vec::with_capacity(self.0.len() as usize);
+        for (key, value) in self.0.iter() {
+            entries.push(format!(
+                "{}: {}",
+                key.0.bind(py).repr()?,
+                value.bind(py).repr()?
+            ));
+        }
+        Ok(format!("TreapMap({{{}}})", entries.join(", ")))
+    }
+
+    #[pyo3(signature = (key, default = None))]
+    fn get(&self, key: &Bound<'_, PyAny>, default: Option<PyObject>) -> PyResult<Option<PyObject>> {
+        Ok(match self.locate(key)? {
+            (n, true) => Some(self.0.nth(n).unwrap().clone_ref(key.py())),
+            (_, false) => default,
+        })
+    }
+
+    #[pyo3(signature = (key, default = None))]
+    fn pop(&mut self, key: &Bound<'_, PyAny>, default: Option<PyObject>) -> PyResult<PyObject> {
+        match (self.locate(key)?, default) {
+            ((n, true), _) => Ok(remove_nth(&mut self.0, n)),
+            (_, Some(default)) => Ok(default),
+            (_, None) => Err(PyKeyError::new_err(key.clone().unbind())),
+        }
+    }
+
+    fn keys(&self, py: Python<'_>) -> Vec<PyObject> {
+        self.0.iter().map(|it| it.0 .0.clone_ref(py)).collect()
+    }
+
+    fn values(&self, py: Python<'_>) -> Vec<PyObject> {
+        self.0.iter().map(|it| it.1.clone_ref(py)).collect()
+    }
+
+    fn items(&self, py: Python<'_>) -> Vec<(PyObject, PyObject)> {
+        self.0.iter().map(|it| item(py, it)).collect()
+    }
+
+    fn rank(&self, key: &Bound<'_, PyAny>) -> PyResult<Option<Size>> {
+        let (n, found) = self.locate(key)?;
+        Ok(found.then_some(n))
+    }
+
+    fn num_lt(&self, key: &Bound<'_, PyAny>) -> PyResult<Size> {
+        Ok(self.locate(key)?.0)
+    }
+
+    fn num_le(&self, key: &Bound<'_, PyAny>) -> PyResult<Size> {
+        let (n, found) = self.locate(key)?;
+        Ok(n + found as Size)
+    }
+
+    fn nth(&self, py: Python<'_>, n: Size) -> PyResult<(PyObject, PyObject)> {
+        match self.0.nth_kv(n) {
+            Some(it) => Ok(item(py, it)),
+            None => Err(PyIndexError::new_err("index out of range")),
+        }
+    }
+
+    fn slice(&self, py: Python<'_>, start: Size, end: Size) -> Vec<(PyObject, PyObject)> {
+        self.0.slice(start..end).map(|it| item(py, it)).collect()
+    }
+
+    fn first(&self, py: Python<'_>) -> Option<(PyObject, PyObject)> {
+        self.0.first_key_value().map(|it| item(py, it))
+    }
+
+    fn last(&self, py: Python<'_>) -> Option<(PyObject, PyObject)> {
+        self.0.last_key_value().map(|it| item(py, it))
+    }
+
+    fn pop_min(&mut self) -> Option<(PyObject, PyObject)> {
+        self.0.pop_min().map(|(key, value)| (key.0, value))
+    }
+
+    fn pop_max(&mut self) -> Option<(PyObject, PyObject)> {
+        self.0.pop_max().map(|(key, value)| (key.0, value))
+    }
+}
+
+#[pyclass(name = "TreapSet")]
+struct PyTreapSet(TreapSet<PyKey>);
+
+impl PyTreapSet {
+    fn locate(&self, key: &Bound<'_, PyAny>) -> PyResult<(Size, bool)> {
+        locate(&self.0 .0, key)
+    }
+}
+
+#[pymethods]
+impl PyTreapSet {
+    #[new]
+    #[pyo3(signature = (items = None))]
+    fn new(items: Option<&Bound<'_, PyAny>>) -> PyResult<Self> {
+        let mut res = Self(TreapSet::new());
+        if let Some(items) = items {
+            for key in items.iter()? {
+                res.add(&key?)?;
+            }
+        }
+        Ok(res)
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len() as usize
+    }
+
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.locate(key)?.1)
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        let keys: Vec<_> = self.0.iter().map(|it| it.0.clone_ref(py)).collect();
+        PyList::new_bound(py, keys).into_any().iter()
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let mut keys = Vec::with_capacity(self.0.len() as usize);
+        for key in self.0.iter() {
+            keys.push(key.0.bind(py).repr()?.to_string());
+        }
+        Ok(format!("TreapSet([{}])", keys.join(", ")))
+    }
+
+    fn add(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        if let (n, false) = self.locate(key)? {
+            insert_nth(&mut self.0 .0, n, key, ());
+        }
+        Ok(())
+    }
+
+    fn discard(&mut self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let (n, found) = self.locate(key)?;
+        if found {
+            remove_nth(&mut self.0 .0, n);
+        }
+        Ok(found)
+    }
+
+    fn remove(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        if self.discard(key)? {
+            Ok(())
+        } else {
+            Err(PyKeyError::new_err(key.clone().unbind()))
+        }
+    }
+
+    fn rank(&self, key: &Bound<'_, PyAny>) -> PyResult<Option<Size>> {
+        let (n, found) = self.locate(key)?;
+        Ok(found.then_some(n))
+    }
+
+    fn num_lt(&self, key: &Bound<'_, PyAny>) -> PyResult<Size> {
+        Ok(self.locate(key)?.0)
+    }
+
+    fn num_le(&self, key: &Bound<'_, PyAny>) -> PyResult<Size> {
+        let (n, found) = self.locate(key)?;
+        Ok(n + found as Size)
+    }
+
+    fn nth(&self, py: Python<'_>, n: Size) -> PyResult<PyObject> {
+        match self.0.nth(n) {
+            Some(key) => Ok(key.0.clone_ref(py)),
+            None => Err(PyIndexError::new_err("index out of range")),
+        }
+    }
+
+    fn slice(&self, py: Python<'_>, start: Size, end: Size) -> Vec<PyObject> {
+        self.0
+            .slice(start..end)
+            .map(|it| it.0.clone_ref(py))
+            .collect()
+    }
+
+    fn first(&self, py: Python<'_>) -> Option<PyObject> {
+        self.0.first().map(|it| it.0.clone_ref(py))
+    }
+
+    fn last(&self, py: Python<'_>) -> Option<PyObject> {
+        self.0.last().map(|it| it.0.clone_ref(py))
+    }
+
+    fn pop_min(&mut self) -> Option<PyObject> {
+        self.0.pop_min().map(|it| it.0)
+    }
+
+    fn pop_max(&mut self) -> Option<PyObject> {
+        self.0.pop_max().map(|it| it.0)
+    }
+}
+
+#[pymodule]
+fn fhq_treap(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyTreapMap>()?;
+    m.add_class::<PyTreapSet>()?;
+    Ok(())
+}
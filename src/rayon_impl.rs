@@ -0,0 +1,277 @@
+use std::{mem, ops::Range};
+
+use rayon::iter::{
+    plumbing::{bridge, Consumer, Producer, ProducerCallback, UnindexedConsumer},
+    IndexedParallelIterator, IntoParallelIterator, ParallelIterator,
+};
+
+use crate::{rng, Iter, NodeData, Size, SortedBuilder, TreapMap, TreapSet};
+
+// Work is split by rank, so every half is itself a contiguous slice of the
+// tree and each piece seeks in O(log n) before iterating.
+pub struct ParIter<'a, K: Ord, V> {
+    map: &'a TreapMap<K, V>,
+    range: Range<Size>,
+}
+
+impl<'a, K: Ord + Sync, V: Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+
+    #[inline]
+    fn opt_len(&self) -> Option<usize> {
+        Some(self.range.len())
+    }
+}
+
+impl<'a, K: Ord + Sync, V: Sync> IndexedParallelIterator for ParIter<'a, K, V> {
+    fn drive<C: Consumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    fn with_producer<CB: ProducerCallback<Self::Item>>(self, callback: CB) -> CB::Output {
+        callback.callback(self)
+    }
+}
+
+impl<'a, K: Ord + Sync, V: Sync> Producer for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.slice(self.range)
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let mid = self.range.start + index as Size;
+        (
+            Self {
+                map: self.map,
+                range: self.range.start..mid,
+            },
+            Self {
+                map: self.map,
+                range: mid..self.range.end,
+            },
+        )
+    }
+}
+
+impl<'a, K: Ord + Sync, V: Sync> IntoParallelIterator for &'a TreapMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type Iter = ParIter<'a, K, V>;
+
+    #[inline]
+    fn into_par_iter(self) -> Self::Iter {
+        ParIter {
+            map: self,
+            range: 0..self.len(),
+        }
+    }
+}
+
+pub struct ParSetIter<'a, K: Ord>(ParIter<'a, K, ()>);
+
+impl<'a, K: Ord + Sync> ParallelIterator for ParSetIter<'a, K> {
+    type Item = &'a K;
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        self.0.map(|it| it.0).drive_unindexed(consumer)
+    }
+
+    #[inline]
+    fn opt_len(&self) -> Option<usize> {
+        self.0.opt_len()
+    }
+}
+
+impl<'a, K: Ord + Sync> IndexedParallelIterator for ParSetIter<'a, K> {
+    fn drive<C: Consumer<Self::Item>>(self, consumer: C) -> C::Result {
+        self.0.map(|it| it.0).drive(consumer)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn with_producer<CB: ProducerCallback<Self::Item>>(self, callback: CB) -> CB::Output {
+        self.0.map(|it| it.0).with_producer(callback)
+    }
+}
+
+impl<'a, K: Ord + Sync> IntoParallelIterator for &'a TreapSet<K> {
+    type Item = &'a K;
+    type Iter = ParSetIter<'a, K>;
+
+    #[inline]
+    fn into_par_iter(self) -> Self::Iter {
+        ParSetIter(self.0.into_par_iter())
+    }
+}
+
+// Each piece rayon splits the input into is built on its own and the pieces
+// are merged back in order. Weights are drawn by position from a single
+// stream, so the tree doesn't depend on how the work was split.
+fn par_build<K: Ord + Send, V: Send>(
+    entries: impl IndexedParallelIterator<Item = (K, V)>,
+) -> TreapMap<K, V> {
+    let stream = rng::random_stream();
+    entries
+        .enumerate()
+        .fold(
+            || SortedBuilder(Vec::new()),
+            |mut builder, (i, (key, value))| {
+                builder.push(NodeData::with_weight(
+                    key,
+                    value,
+                    rng::stream_weight(stream, i as u64),
+                ));
+                builder
+            },
+        )
+        .map(SortedBuilder::finish)
+        .reduce(TreapMap::new, TreapMap::merge)
+}
+
+impl<K: Ord + Send, V: Send> TreapMap<K, V> {
+    // Like `from_unique_sorted_iter`, but spread across rayon's thread pool.
+    pub fn par_from_unique_sorted_vec(entries: Vec<(K, V)>) -> Self {
+        par_build(entries.into_par_iter())
+    }
+}
+
+impl<K: Ord + Send> TreapSet<K> {
+    pub fn par_from_unique_sorted_vec(keys: Vec<K>) -> Self {
+        Self(par_build(keys.into_par_iter().map(|key| (key, ()))))
+    }
+}
+
+// Below this many entries in total, a set operation finishes on one thread.
+const PAR_CUTOFF: Size = 1 << 12;
+
+// The same recursions as `union`, `intersect` and `subtract`, with the two
+// halves left after each split handed to `rayon::join`.
+impl<K: Ord + Send, V: Send> TreapMap<K, V> {
+    #[inline]
+    fn is_small(x: &Self, y: &Self) -> bool {
+        x.len().saturating_add(y.len()) < PAR_CUTOFF
+    }
+
+    fn par_union(x: Self, y: Self) -> Self {
+        if Self::is_small(&x, &y) {
+            return Self::union(x, y);
+        }
+        let Some(mut x) = x.into_root() else { return y };
+        let Some(mut y) = y.into_root() else {
+            return x.into();
+        };
+        if x.weight < y.weight {
+            let (l, _, r) = Self::from(y).split3(&x.key);
+            let [xl, xr] = mem::take(&mut x.children);
+            let (l, r) = rayon::join(|| Self::par_union(xl, l), || Self::par_union(xr, r));
+            x.children = [l, r];
+            x.maintain();
+            x.into()
+        } else {
+            let (l, m, r) = Self::from(x).split3(&y.key);
+            let [yl, yr] = mem::take(&mut y.children);
+            let (l, r) = rayon::join(|| Self::par_union(l, yl), || Self::par_union(r, yr));
+            y.children = [l, r];
+            if let Some(m) = m.into_root() {
+                y.key = m.key;
+                y.value = m.value;
+            }
+            y.maintain();
+            y.into()
+        }
+    }
+
+    fn par_intersect(x: Self, y: Self) -> Self {
+        if Self::is_small(&x, &y) {
+            return Self::intersect(x, y);
+        }
+        let Some(mut x) = x.into_root() else {
+            return Self::new();
+        };
+        let Some(mut y) = y.into_root() else {
+            return Self::new();
+        };
+        if x.weight < y.weight {
+            let (l, m, r) = Self::from(y).split3(&x.key);
+            let [xl, xr] = mem::take(&mut x.children);
+            let (l, r) = rayon::join(|| Self::par_intersect(xl, l), || Self::par_intersect(xr, r));
+            if m.is_empty() {
+                return Self::merge(l, r);
+            }
+            x.children = [l, r];
+            x.maintain();
+            x.into()
+        } else {
+            let (l, m, r) = Self::from(x).split3(&y.key);
+            let [yl, yr] = mem::take(&mut y.children);
+            let (l, r) = rayon::join(|| Self::par_intersect(l, yl), || Self::par_intersect(r, yr));
+            Self::merge(Self::merge(l, m), r)
+        }
+    }
+
+    fn par_subtract(x: Self, y: Self) -> Self {
+        if Self::is_small(&x, &y) {
+            return Self::subtract(x, y);
+        }
+        let Some(mut x) = x.into_root() else {
+            return Self::new();
+        };
+        let Some(mut y) = y.into_root() else {
+            return x.into();
+        };
+        if x.weight < y.weight {
+            let (l, m, r) = Self::from(y).split3(&x.key);
+            let [xl, xr] = mem::take(&mut x.children);
+            let (l, r) = rayon::join(|| Self::par_subtract(xl, l), || Self::par_subtract(xr, r));
+            if !m.is_empty() {
+                return Self::merge(l, r);
+            }
+            x.children = [l, r];
+            x.maintain();
+            x.into()
+        } else {
+            let (l, _, r) = Self::from(x).split3(&y.key);
+            let [yl, yr] = mem::take(&mut y.children);
+            let (l, r) = rayon::join(|| Self::par_subtract(l, yl), || Self::par_subtract(r, yr));
+            Self::merge(l, r)
+        }
+    }
+}
+
+impl<K: Ord + Send> TreapSet<K> {
+    #[inline]
+    pub fn par_union(self, other: Self) -> Self {
+        let res = Self(TreapMap::par_union(self.0, other.0));
+        res.0.check();
+        res
+    }
+
+    #[inline]
+    pub fn par_intersect(self, other: Self) -> Self {
+        let res = Self(TreapMap::par_intersect(self.0, other.0));
+        res.0.check();
+        res
+    }
+
+    #[inline]
+    pub fn par_subtract(self, other: Self) -> Self {
+        let res = Self(TreapMap::par_subtract(self.0, other.0));
+        res.0.check();
+        res
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    cell::Cell,
+    hash::{Hash, Hasher},
+};
+
+use crate::Weight;
+
+// Weights come from a per-thread SplitMix64 stream. With `random_seed` each
+// thread starts from a random seed taken from std's `RandomState`; without it
+// (e.g. on `wasm32-unknown-unknown`) the seed is fixed, so runs are
+// reproducible unless `seed_weights` is called.
+thread_local! {
+    static STATE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+#[cfg(feature = "random_seed")]
+fn initial_seed() -> u64 {
+    use std::{collections::hash_map::RandomState, hash::BuildHasher};
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(not(feature = "random_seed"))]
+fn initial_seed() -> u64 {
+    0x853c_49e6_748f_ea9b
+}
+
+pub fn seed_weights(seed: u64) {
+    STATE.with(|state| state.set(Some(seed)));
+}
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[inline]
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[inline]
+fn next() -> u64 {
+    STATE.with(|state| {
+        let z = state.get().unwrap_or_else(initial_seed).wrapping_add(GAMMA);
+        state.set(Some(z));
+        mix(z)
+    })
+}
+
+#[inline]
+pub(crate) fn random_weight() -> Weight {
+    (next() >> (64 - Weight::BITS)) as Weight
+}
+
+// Uniform in the open interval (0, 1).
+#[inline]
+pub(crate) fn random_unit() -> f64 {
+    ((next() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+}
+
+// A stream for `stream_weight`, which yields its `i`th weight directly, so
+// weights can be drawn out of order and on other threads yet still follow from
+// the current thread's seed.
+#[cfg(feature = "rayon")]
+pub(crate) fn random_stream() -> u64 {
+    next()
+}
+
+#[cfg(feature = "rayon")]
+#[inline]
+pub(crate) fn stream_weight(stream: u64, i: u64) -> Weight {
+    (mix(stream.wrapping_add(i.wrapping_add(1).wrapping_mul(GAMMA))) >> (64 - Weight::BITS))
+        as Weight
+}
+
+// A fixed, seedless hash, so a key gets the same weight on every run and
+// machine. Integers are mixed by value rather than by their native-endian bytes.
+struct KeyHasher(u64);
+
+impl KeyHasher {
+    #[inline]
+    fn mix(&mut self, x: u64) {
+        self.0 = (self.0.rotate_left(5) ^ x).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.mix(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        let mut tail = [0; 8];
+        tail[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+        self.mix(u64::from_le_bytes(tail) ^ (bytes.len() as u64) << 56);
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.mix(i as u64);
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.mix(i as u64);
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.mix(i as u64);
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.mix(i);
+    }
+
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.mix(i as u64);
+        self.mix((i >> 64) as u64);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.mix(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        mix(self.0)
+    }
+}
+
+pub(crate) fn hash_weight<K: Hash + ?Sized>(key: &K) -> Weight {
+    let mut hasher = KeyHasher(0);
+    key.hash(&mut hasher);
+    (hasher.finish() >> (64 - Weight::BITS)) as Weight
+}
@@ -0,0 +1,173 @@
+use std::{borrow::Borrow, fmt, marker::PhantomData, ops::RangeBounds};
+
+use serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{Iter, NodeData, SetIter, SortedBuilder, TreapMap, TreapSet};
+
+impl<K: Ord + Serialize, V: Serialize> Serialize for TreapMap<K, V> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<K: Ord + Serialize> Serialize for TreapSet<K> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+pub struct SerializeRange<'a, K: Ord, V>(Iter<'a, K, V>);
+
+impl<'a, K: Ord + Serialize, V: Serialize> Serialize for SerializeRange<'a, K, V> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.clone())
+    }
+}
+
+pub struct SerializeSetRange<'a, K: Ord>(SetIter<'a, K>);
+
+impl<'a, K: Ord + Serialize> Serialize for SerializeSetRange<'a, K> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.clone())
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    #[inline]
+    pub fn serialize_range<Q: Ord + ?Sized, R: RangeBounds<Q>>(
+        &self,
+        range: R,
+    ) -> SerializeRange<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        SerializeRange(self.range(range))
+    }
+
+    pub fn deserialize_extend<'de, D: Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+    ) -> Result<(), D::Error>
+    where
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+    {
+        let mut chunk = Self::deserialize(deserializer)?;
+        self.append(&mut chunk);
+        Ok(())
+    }
+}
+
+impl<K: Ord> TreapSet<K> {
+    #[inline]
+    pub fn serialize_range<Q: Ord + ?Sized, R: RangeBounds<Q>>(
+        &self,
+        range: R,
+    ) -> SerializeSetRange<'_, K>
+    where
+        K: Borrow<Q>,
+    {
+        SerializeSetRange(self.range(range))
+    }
+
+    pub fn deserialize_extend<'de, D: Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+    ) -> Result<(), D::Error>
+    where
+        K: Deserialize<'de>,
+    {
+        let mut chunk = Self::deserialize(deserializer)?;
+        self.append(&mut chunk);
+        Ok(())
+    }
+}
+
+// Entries usually arrive in ascending order, since that is how they are
+// serialized, so they go straight onto the right spine; anything else falls
+// back to a regular insertion and overrides earlier duplicates.
+pub(crate) struct Collector<K: Ord, V> {
+    sorted: SortedBuilder<K, V>,
+    rest: TreapMap<K, V>,
+}
+
+impl<K: Ord, V> Collector<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            sorted: SortedBuilder(Vec::new()),
+            rest: TreapMap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, key: K, value: V) {
+        if self.sorted.0.last().is_none_or(|it| it.key < key) {
+            self.sorted.push(NodeData::new(key, value));
+        } else {
+            self.rest.insert(key, value);
+        }
+    }
+
+    pub(crate) fn finish(self) -> TreapMap<K, V> {
+        let mut res = self.sorted.finish();
+        let mut rest = self.rest;
+        res.append(&mut rest);
+        res
+    }
+}
+
+struct MapVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for MapVisitor<K, V> {
+    type Value = TreapMap<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut collector = Collector::new();
+        while let Some((key, value)) = access.next_entry()? {
+            collector.push(key, value);
+        }
+        Ok(collector.finish())
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for TreapMap<K, V> {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+struct SetVisitor<K>(PhantomData<K>);
+
+impl<'de, K: Ord + Deserialize<'de>> Visitor<'de> for SetVisitor<K> {
+    type Value = TreapSet<K>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut collector = Collector::new();
+        while let Some(key) = access.next_element()? {
+            collector.push(key, ());
+        }
+        Ok(TreapSet(collector.finish()))
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>> Deserialize<'de> for TreapSet<K> {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SetVisitor(PhantomData))
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    fmt::{self, Debug},
+    ops::Deref,
+};
+
+use crate::{PersistentIter, PersistentTreapMap};
+
+// A read-only version of a `PersistentTreapMap` that any number of handles
+// (and their iterators) share, so the map itself can keep changing while they
+// are alive. Taking or cloning one only shares the root; the map copies the
+// nodes it changes from then on instead of touching the snapshot's.
+pub struct Snapshot<K: Ord, V>(PersistentTreapMap<K, V>);
+
+impl<K: Ord, V> Clone for Snapshot<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K: Ord, V> Deref for Snapshot<K, V> {
+    type Target = PersistentTreapMap<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for Snapshot<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Snapshot").field(&self.0).finish()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a Snapshot<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = PersistentIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<K: Ord, V> From<PersistentTreapMap<K, V>> for Snapshot<K, V> {
+    #[inline]
+    fn from(map: PersistentTreapMap<K, V>) -> Self {
+        Self(map)
+    }
+}
+
+impl<K: Ord, V> PersistentTreapMap<K, V> {
+    #[inline]
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        Snapshot(self.clone())
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    iter::FusedIterator,
+    mem, slice,
+};
+
+use crate::{Iter, Size, TreapMap};
+
+// Up to `N` entries live sorted in the `Inline` array, in its first `len`
+// slots; past that the map spills into a `TreapMap` and stays there until it
+// is emptied.
+#[allow(clippy::large_enum_variant)]
+enum Repr<K: Ord, V, const N: usize> {
+    Inline(usize, [Option<(K, V)>; N]),
+    Tree(TreapMap<K, V>),
+}
+
+// A `TreapMap` that keeps small maps in a sorted inline array, so they cost no
+// allocations and are searched by a linear scan.
+pub struct SmallTreapMap<K: Ord, V, const N: usize = 8>(Repr<K, V, N>);
+
+#[inline]
+fn entry<K, V>(it: &Option<(K, V)>) -> (&K, &V) {
+    let (key, value) = it.as_ref().unwrap();
+    (key, value)
+}
+
+impl<K: Ord, V, const N: usize> Default for SmallTreapMap<K, V, N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, const N: usize> Clone for SmallTreapMap<K, V, N> {
+    fn clone(&self) -> Self {
+        Self(match &self.0 {
+            Repr::Inline(len, entries) => Repr::Inline(*len, entries.clone()),
+            Repr::Tree(map) => Repr::Tree(map.clone()),
+        })
+    }
+}
+
+impl<K: Ord + Debug, V: Debug, const N: usize> Debug for SmallTreapMap<K, V, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V: PartialEq, const N: usize> PartialEq for SmallTreapMap<K, V, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord, V: Eq, const N: usize> Eq for SmallTreapMap<K, V, N> {}
+
+impl<K: Ord, V, const N: usize> FromIterator<(K, V)> for SmallTreapMap<K, V, N> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut res = Self::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K: Ord, V, const N: usize> Extend<(K, V)> for SmallTreapMap<K, V, N> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V, const N: usize> IntoIterator for &'a SmallTreapMap<K, V, N> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = SmallIter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V, const N: usize> SmallTreapMap<K, V, N> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(Repr::Inline(0, std::array::from_fn(|_| None)))
+    }
+
+    #[inline]
+    pub fn len(&self) -> Size {
+        match &self.0 {
+            Repr::Inline(len, _) => *len as Size,
+            Repr::Tree(map) => map.len(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Zero while the entries are inline.
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        match &self.0 {
+            Repr::Inline(..) => 0,
+            Repr::Tree(map) => map.memory_usage(),
+        }
+    }
+
+    // Whether the entries still live in the inline array.
+    #[inline]
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline(..))
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn into_map(self) -> TreapMap<K, V> {
+        match self.0 {
+            Repr::Inline(len, entries) => {
+                TreapMap::from_unique_sorted_iter(entries.into_iter().take(len).map(Option::unwrap))
+            }
+            Repr::Tree(map) => map,
+        }
+    }
+
+    // The index of the first inline entry not less than `key`, and whether it
+    // is equal to it.
+    fn search<Q: Ord + ?Sized>(entries: &[Option<(K, V)>], key: &Q) -> (usize, bool)
+    where
+        K: Borrow<Q>,
+    {
+        for (i, it) in entries.iter().enumerate() {
+            let k = entry(it).0.borrow();
+            if k >= key {
+                return (i, k == key);
+            }
+        }
+        (entries.len(), false)
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        match &self.0 {
+            Repr::Inline(len, entries) => match Self::search(&entries[..*len], key) {
+                (i, true) => Some(entry(&entries[i]).1),
+                _ => None,
+            },
+            Repr::Tree(map) => map.get(key),
+        }
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        match &mut self.0 {
+            Repr::Inline(len, entries) => match Self::search(&entries[..*len], key) {
+                (i, true) => entries[i].as_mut().map(|it| &mut it.1),
+                _ => None,
+            },
+            Repr::Tree(map) => map.get_mut(key),
+        }
+    }
+
+    #[inline]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (len, entries) = match &mut self.0 {
+            Repr::Inline(len, entries) => (len, entries),
+            Repr::Tree(map) => return map.insert(key, value),
+        };
+        let (i, found) = Self::search(&entries[..*len], &key);
+        if found {
+            return Some(mem::replace(&mut entries[i].as_mut().unwrap().1, value));
+        }
+        if *len < N {
+            entries[i..=*len].rotate_right(1);
+            entries[i] = Some((key, value));
+            *len += 1;
+            return None;
+        }
+        let mut map =
+            TreapMap::from_unique_sorted_iter(entries.iter_mut().map(|it| it.take().unwrap()));
+        map.insert(key, value);
+        self.0 = Repr::Tree(map);
+        None
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        match &mut self.0 {
+            Repr::Inline(len, entries) => {
+                let (i, true) = Self::search(&entries[..*len], key) else {
+                    return None;
+                };
+                let (_, value) = entries[i].take().unwrap();
+                entries[i..*len].rotate_left(1);
+                *len -= 1;
+                Some(value)
+            }
+            Repr::Tree(map) => {
+                let res = map.remove(key);
+                if map.is_empty() {
+                    self.clear();
+                }
+                res
+            }
+        }
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, V)> {
+        match &mut self.0 {
+            Repr::Inline(0, _) => None,
+            Repr::Inline(len, entries) => {
+                let res = entries[0].take();
+                entries[..*len].rotate_left(1);
+                *len -= 1;
+                res
+            }
+            Repr::Tree(map) => {
+                let res = map.pop_min();
+                if map.is_empty() {
+                    self.clear();
+                }
+                res
+            }
+        }
+    }
+
+    pub fn pop_max(&mut self) -> Option<(K, V)> {
+        match &mut self.0 {
+            Repr::Inline(0, _) => None,
+            Repr::Inline(len, entries) => {
+                *len -= 1;
+                entries[*len].take()
+            }
+            Repr::Tree(map) => {
+                let res = map.pop_max();
+                if map.is_empty() {
+                    self.clear();
+                }
+                res
+            }
+        }
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        match &self.0 {
+            Repr::Inline(len, entries) => entries[..*len].first().map(entry),
+            Repr::Tree(map) => map.first_key_value(),
+        }
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        match &self.0 {
+            Repr::Inline(len, entries) => entries[..*len].last().map(entry),
+            Repr::Tree(map) => map.last_key_value(),
+        }
+    }
+
+    pub fn iter(&self) -> SmallIter<'_, K, V> {
+        SmallIter(match &self.0 {
+            Repr::Inline(len, entries) => IterRepr::Inline(entries[..*len].iter()),
+            Repr::Tree(map) => IterRepr::Tree(map.iter()),
+        })
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum IterRepr<'a, K: Ord, V> {
+    Inline(slice::Iter<'a, Option<(K, V)>>),
+    Tree(Iter<'a, K, V>),
+}
+
+pub struct SmallIter<'a, K: Ord, V>(IterRepr<'a, K, V>);
+
+impl<'a, K: Ord, V> Clone for SmallIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        Self(match &self.0 {
+            IterRepr::Inline(it) => IterRepr::Inline(it.clone()),
+            IterRepr::Tree(it) => IterRepr::Tree(it.clone()),
+        })
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for SmallIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            IterRepr::Inline(it) => it.next().map(entry),
+            IterRepr::Tree(it) => it.next(),
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.0 {
+            IterRepr::Inline(it) => it.size_hint(),
+            IterRepr::Tree(it) => it.size_hint(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for SmallIter<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            IterRepr::Inline(it) => it.next_back().map(entry),
+            IterRepr::Tree(it) => it.next_back(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for SmallIter<'a, K, V> {}
+
+impl<'a, K: Ord, V> FusedIterator for SmallIter<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for SmallIter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
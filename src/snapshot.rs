@@ -0,0 +1,239 @@
+use std::io::{self, Read, Write};
+
+use crate::{len_u32, NodeData, TreapMap, TreapSet, Weight, LEFT, RIGHT};
+
+pub trait SnapshotItem: Sized {
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()>;
+    fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! impl_snapshot_item {
+    ($($t:ty),*) => {
+        $(impl SnapshotItem for $t {
+            #[inline]
+            fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+
+            #[inline]
+            fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self> {
+                let mut buf = [0; std::mem::size_of::<$t>()];
+                r.read_exact(&mut buf)?;
+                Ok(<$t>::from_le_bytes(buf))
+            }
+        })*
+    };
+}
+impl_snapshot_item!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl SnapshotItem for usize {
+    #[inline]
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u64).write_to(w)
+    }
+
+    #[inline]
+    fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self> {
+        usize::try_from(u64::read_from(r)?).map_err(invalid_data)
+    }
+}
+
+impl SnapshotItem for isize {
+    #[inline]
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        (*self as i64).write_to(w)
+    }
+
+    #[inline]
+    fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self> {
+        isize::try_from(i64::read_from(r)?).map_err(invalid_data)
+    }
+}
+
+impl SnapshotItem for () {
+    #[inline]
+    fn write_to<W: Write + ?Sized>(&self, _w: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn read_from<R: Read + ?Sized>(_r: &mut R) -> io::Result<Self> {
+        Ok(())
+    }
+}
+
+impl SnapshotItem for bool {
+    #[inline]
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u8).write_to(w)
+    }
+
+    fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self> {
+        match u8::read_from(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+}
+
+impl SnapshotItem for char {
+    #[inline]
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u32).write_to(w)
+    }
+
+    #[inline]
+    fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self> {
+        char::from_u32(u32::read_from(r)?).ok_or_else(|| invalid_data("invalid char"))
+    }
+}
+
+impl SnapshotItem for String {
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        let len = u32::try_from(self.len()).map_err(invalid_data)?;
+        len.write_to(w)?;
+        w.write_all(self.as_bytes())
+    }
+
+    fn read_from<R: Read + ?Sized>(r: &mut R) -> io::Result<Self> {
+        let len = u32::read_from(r)?;
+        let bytes = read_bytes(r, len as u64)?;
+        String::from_utf8(bytes).map_err(invalid_data)
+    }
+}
+
+// Weights are stored scaled to 32 bits, so a snapshot reads back under any
+// weight width with its heap order intact, if with more ties.
+#[inline]
+fn weight_to_u32(weight: Weight) -> u32 {
+    ((weight as u64) << (64 - Weight::BITS) >> 32) as u32
+}
+
+#[inline]
+fn weight_from_u32(weight: u32) -> Weight {
+    ((weight as u64) << 32 >> (64 - Weight::BITS)) as Weight
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+// Reads through `take` so that a corrupted length cannot trigger a huge
+// allocation up front.
+fn read_bytes<R: Read + ?Sized>(r: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+// Layout: the node count, then two bits per node (has left child, has right
+// child) in pre-order, then every node's weight, key and value in the same
+// order.
+impl<K: Ord + SnapshotItem, V: SnapshotItem> TreapMap<K, V> {
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        len_u32(self.len())
+            .ok_or_else(|| invalid_data("too many entries"))?
+            .write_to(&mut w)?;
+        let mut bits = vec![0u8; (self.len() as usize * 2).div_ceil(8)];
+        let mut nodes = Vec::with_capacity(self.len() as usize);
+        let mut stack: Vec<&NodeData<K, V>> = self.0.as_deref().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            let bit = nodes.len() * 2;
+            for dir in [RIGHT, LEFT] {
+                if let Some(child) = node.children[dir].0.as_deref() {
+                    bits[(bit + dir) / 8] |= 1 << ((bit + dir) % 8);
+                    stack.push(child);
+                }
+            }
+            nodes.push(node);
+        }
+        w.write_all(&bits)?;
+        for node in nodes {
+            weight_to_u32(node.weight).write_to(&mut w)?;
+            node.key.write_to(&mut w)?;
+            node.value.write_to(&mut w)?;
+        }
+        w.flush()
+    }
+
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<Self> {
+        let len = u32::read_from(&mut r)? as usize;
+        let bits = read_bytes(&mut r, (len as u64 * 2).div_ceil(8))?;
+        let bit = |i: usize| bits[i / 8] >> (i % 8) & 1 != 0;
+
+        // Nodes whose subtrees are still being read, along with whether the
+        // left subtree is the one pending and whether a right subtree follows.
+        let mut stack = Vec::new();
+        let mut root = None;
+        for i in 0..len {
+            if root.is_some() {
+                return Err(invalid_data("trailing nodes in snapshot"));
+            }
+            let weight = weight_from_u32(u32::read_from(&mut r)?);
+            count!(allocations);
+            let mut node = Box::new(NodeData {
+                children: Default::default(),
+                size: 1,
+                key: K::read_from(&mut r)?,
+                value: V::read_from(&mut r)?,
+
+                weight,
+            });
+            let (has_left, has_right) = (bit(i * 2), bit(i * 2 + 1));
+            if has_left || has_right {
+                stack.push((node, has_left, has_right));
+                continue;
+            }
+            loop {
+                node.maintain();
+                let Some((mut parent, left, has_right)) = stack.pop() else {
+                    root = Some(node);
+                    break;
+                };
+                if node.weight < parent.weight {
+                    return Err(invalid_data("heap order violated in snapshot"));
+                }
+                if left {
+                    parent.children[LEFT] = node.into();
+                    if has_right {
+                        stack.push((parent, false, true));
+                        break;
+                    }
+                } else {
+                    parent.children[RIGHT] = node.into();
+                }
+                node = parent;
+            }
+        }
+        if !stack.is_empty() {
+            return Err(invalid_data("truncated snapshot structure"));
+        }
+        let res = Self(root);
+        let mut iter = res.iter().map(|it| it.0);
+        if let Some(mut prev) = iter.next() {
+            for key in iter {
+                if prev >= key {
+                    return Err(invalid_data("keys out of order in snapshot"));
+                }
+                prev = key;
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl<K: Ord + SnapshotItem> TreapSet<K> {
+    #[inline]
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        self.0.write_to(w)
+    }
+
+    #[inline]
+    pub fn read_from<R: Read>(r: R) -> io::Result<Self> {
+        TreapMap::read_from(r).map(Self)
+    }
+}
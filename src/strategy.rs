@@ -0,0 +1,114 @@
+use proptest::{
+    collection::{vec, SizeRange},
+    prelude::*,
+};
+
+use crate::{TreapMap, TreapSet};
+
+#[derive(Clone, Debug)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Remove(K),
+    PopMin,
+    PopMax,
+}
+
+impl<K: Ord, V> Op<K, V> {
+    pub fn apply(self, map: &mut TreapMap<K, V>) {
+        match self {
+            Op::Insert(key, value) => {
+                map.insert(key, value);
+            }
+            Op::Remove(key) => {
+                map.remove(&key);
+            }
+            Op::PopMin => {
+                map.pop_min();
+            }
+            Op::PopMax => {
+                map.pop_max();
+            }
+        }
+    }
+}
+
+pub fn op<K: Strategy + Clone, V: Strategy>(
+    key: K,
+    value: V,
+) -> impl Strategy<Value = Op<K::Value, V::Value>>
+where
+    K::Value: Clone,
+    V::Value: Clone,
+{
+    prop_oneof![
+        4 => (key.clone(), value).prop_map(|(k, v)| Op::Insert(k, v)),
+        2 => key.prop_map(Op::Remove),
+        1 => Just(Op::PopMin),
+        1 => Just(Op::PopMax),
+    ]
+}
+
+pub fn ops<K: Strategy + Clone, V: Strategy>(
+    key: K,
+    value: V,
+    len: impl Into<SizeRange>,
+) -> impl Strategy<Value = Vec<Op<K::Value, V::Value>>>
+where
+    K::Value: Clone,
+    V::Value: Clone,
+{
+    vec(op(key, value), len)
+}
+
+// `size` bounds the number of generated entries; duplicate keys collapse
+// afterwards, with the last value winning.
+pub fn treap_map<K: Strategy, V: Strategy>(
+    key: K,
+    value: V,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = TreapMap<K::Value, V::Value>>
+where
+    K::Value: Ord,
+{
+    vec((key, value), size).prop_map(|it| {
+        let mut map = TreapMap::new();
+        for (key, value) in it {
+            map.insert(key, value);
+        }
+        map
+    })
+}
+
+pub fn treap_set<K: Strategy>(
+    key: K,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = TreapSet<K::Value>>
+where
+    K::Value: Ord,
+{
+    vec(key, size).prop_map(|it| {
+        let mut set = TreapSet::new();
+        for key in it {
+            set.insert(key);
+        }
+        set
+    })
+}
+
+pub fn treap_map_from_ops<K: Strategy + Clone, V: Strategy>(
+    key: K,
+    value: V,
+    len: impl Into<SizeRange>,
+) -> impl Strategy<Value = TreapMap<K::Value, V::Value>>
+where
+    K::Value: Ord + Clone,
+    V::Value: Clone,
+{
+    ops(key, value, len).prop_map(|ops| {
+        let mut map = TreapMap::new();
+        for op in ops {
+            op.apply(&mut map);
+        }
+        map
+    })
+}
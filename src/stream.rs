@@ -0,0 +1,66 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use crate::{Iter, SetIter, TreapMap, TreapSet};
+
+// Yields `Pending` (after waking itself) once every `yield_every` items, so a
+// long walk hands control back to the executor instead of running to
+// completion in a single poll.
+pub struct IterStream<I> {
+    iter: I,
+    yield_every: usize,
+    budget: usize,
+}
+
+impl<I: Iterator> IterStream<I> {
+    pub fn new(iter: I, yield_every: usize) -> Self {
+        assert!(yield_every != 0, "yield interval must be non-zero");
+        Self {
+            iter,
+            yield_every,
+            budget: yield_every,
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.budget == 0 {
+            self.budget = self.yield_every;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.budget -= 1;
+        Poll::Ready(self.iter.next())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    #[inline]
+    pub fn stream(&self, yield_every: usize) -> IterStream<Iter<'_, K, V>> {
+        IterStream::new(self.iter(), yield_every)
+    }
+}
+
+impl<K: Ord> TreapSet<K> {
+    #[inline]
+    pub fn stream(&self, yield_every: usize) -> IterStream<SetIter<'_, K>> {
+        IterStream::new(self.iter(), yield_every)
+    }
+}
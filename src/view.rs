@@ -0,0 +1,104 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug},
+    ops::{Range, RangeBounds},
+};
+
+use crate::{rank_bounds, Iter, Size, TreapMap};
+
+// A window of the ranks `start..end` of `map`. Ranks passed to the view's
+// methods are relative to `start`.
+pub struct TreapView<'a, K: Ord, V> {
+    map: &'a TreapMap<K, V>,
+    start: Size,
+    end: Size,
+}
+
+impl<'a, K: Ord, V> Clone for TreapView<'a, K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K: Ord, V> Copy for TreapView<'a, K, V> {}
+
+impl<'a, K: Ord + Debug, V: Debug> Debug for TreapView<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K: Ord, V> TreapView<'a, K, V> {
+    #[inline]
+    pub fn len(&self) -> Size {
+        self.end - self.start
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    #[inline]
+    pub fn nth(&self, n: Size) -> Option<(&'a K, &'a V)> {
+        if n >= self.len() {
+            return None;
+        }
+        self.map.nth_kv(self.start + n)
+    }
+
+    #[inline]
+    pub fn num_lt<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.map.num_lt(key).clamp(self.start, self.end) - self.start
+    }
+
+    #[inline]
+    pub fn num_le<Q: Ord + ?Sized>(&self, key: &Q) -> Size
+    where
+        K: Borrow<Q>,
+    {
+        self.map.num_le(key).clamp(self.start, self.end) - self.start
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'a, K, V> {
+        self.map.slice(self.start..self.end)
+    }
+
+    #[inline]
+    pub fn view(&self, range: impl RangeBounds<Size>) -> Self {
+        let Range { start, end } = rank_bounds(&range, self.len());
+        let start = start.min(end);
+        Self {
+            map: self.map,
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for TreapView<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    #[inline]
+    pub fn view(&self, range: impl RangeBounds<Size>) -> TreapView<'_, K, V> {
+        TreapView {
+            map: self,
+            start: 0,
+            end: self.len(),
+        }
+        .view(range)
+    }
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "epoch")]
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicIsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use fhq_treap::{EpochTreapMap, PersistentTreapMap, Size};
+
+#[test]
+fn readers_see_only_published_versions() {
+    let mut map: EpochTreapMap<u32, u32> = (0..10)
+        .map(|i| (i, i))
+        .collect::<PersistentTreapMap<_, _>>()
+        .into();
+    let reader = map.reader();
+    map.insert(10, 10);
+    map.remove(&0);
+    assert_eq!(reader.read().len(), 10);
+    assert!(reader.read().contains_key(&0));
+
+    let before = reader.read();
+    let loaded = reader.load();
+    map.publish();
+    let after = reader.read();
+    assert!(before.contains_key(&0) && !before.contains_key(&10));
+    assert!(!after.contains_key(&0) && after.contains_key(&10));
+    drop((before, after));
+
+    // Later changes copy the shared nodes instead of touching them.
+    *map.get_mut(&5).unwrap() = 50;
+    map.publish();
+    assert_eq!(loaded.get(&5), Some(&5));
+    assert_eq!(reader.read().get(&5), Some(&50));
+    drop(map);
+    assert_eq!(reader.read().get(&5), Some(&50));
+}
+
+#[test]
+fn publish_without_changes_keeps_the_version() {
+    let mut map = EpochTreapMap::new();
+    map.insert(1, 1);
+    map.publish();
+    let reader = map.reader();
+    let loaded = reader.load();
+    map.publish();
+    assert!(reader.load().ptr_eq(&loaded));
+    map.insert(2, 2);
+    map.publish();
+    assert!(!reader.load().ptr_eq(&loaded));
+}
+
+#[test]
+fn concurrent_readers_see_consistent_versions() {
+    const N: u32 = 500;
+    const TOTAL: i64 = 100 * N as i64;
+    let mut map: EpochTreapMap<u32, i64> = (0..N)
+        .map(|i| (i, 100))
+        .collect::<PersistentTreapMap<_, _>>()
+        .into();
+    let stop = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..4)
+        .map(|t| {
+            let reader = map.reader();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut reads = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let sum: i64 = if t % 2 == 0 {
+                        reader.read().iter().map(|it| it.1).sum()
+                    } else {
+                        reader.load().iter().map(|it| it.1).sum()
+                    };
+                    assert_eq!(sum, TOTAL);
+                    assert_eq!(reader.read().len(), N as Size);
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+    // Every published version moves an amount between two keys, and some
+    // also remove and reinsert a key, so a torn read would show up in the
+    // sum or the length.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for i in 0..20_000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let (a, b) = ((state % N as u64) as u32, ((state >> 32) % N as u64) as u32);
+        *map.get_mut(&a).unwrap() -= 7;
+        *map.get_mut(&b).unwrap() += 7;
+        if i % 5 == 0 {
+            let value = map.remove(&b).unwrap();
+            map.insert(b, value);
+        }
+        map.publish();
+    }
+    stop.store(true, Ordering::Relaxed);
+    for reader in readers {
+        assert!(reader.join().unwrap() > 0);
+    }
+    assert_eq!(map.iter().map(|it| it.1).sum::<i64>(), TOTAL);
+}
+
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+struct Counted;
+
+impl Counted {
+    fn new() -> Self {
+        LIVE.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Clone for Counted {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        LIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn replaced_versions_are_reclaimed() {
+    {
+        let mut map = EpochTreapMap::new();
+        let reader = map.reader();
+        let handle = thread::spawn(move || {
+            for _ in 0..1000 {
+                let _ = reader.read().len();
+            }
+        });
+        for i in 0..1000u32 {
+            map.insert(i % 100, Counted::new());
+            map.publish();
+        }
+        handle.join().unwrap();
+    }
+    for _ in 0..1000 {
+        crossbeam_epoch::pin().flush();
+    }
+    assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+}